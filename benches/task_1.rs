@@ -0,0 +1,223 @@
+use std::fs;
+use std::io::{IoSlice, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use nxlog_task::task_1::log_backend::{self, Format, LogBackend, Theme};
+use nxlog_task::task_1::{self, LogLevel, LogOptions, LogType, MultiLine};
+
+const MESSAGE: &str = "Connection accepted from 192.168.0.12:51234";
+const MULTI_LINE_MESSAGE: &str = "Request failed:\n  at handler\n  at router\n  at server";
+
+// A fresh directory for the files of a single bench
+fn bench_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("nxlog_task_bench_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Failed to create bench directory");
+    dir
+}
+
+fn bench_format(c: &mut Criterion) {
+    let mut group = c.benchmark_group("format");
+    // One record per iteration, so criterion reports records/sec
+    group.throughput(Throughput::Elements(1));
+
+    for multi_line in [
+        MultiLine::Raw,
+        MultiLine::Indent,
+        MultiLine::Escape,
+        MultiLine::Frame,
+    ] {
+        group.bench_function(format!("join_{:?}", multi_line), |b| {
+            b.iter(|| multi_line.join(black_box("[INFO]"), black_box(MULTI_LINE_MESSAGE)))
+        });
+    }
+
+    group.bench_function("escape_clean", |b| {
+        b.iter(|| task_1::escape_control_chars(black_box(MESSAGE)))
+    });
+    group.bench_function("escape_dirty", |b| {
+        b.iter(|| task_1::escape_control_chars(black_box("\x1b[31mred\x1b[0m text")))
+    });
+
+    group.bench_function("record_into", |b| {
+        let options = LogOptions::default();
+        b.iter(|| {
+            task_1::with_record_buffer(|buffer| {
+                task_1::format_record_into(&options, buffer, LogLevel::Info, black_box(MESSAGE));
+                buffer.len()
+            })
+        })
+    });
+
+    group.bench_function("plain", |b| {
+        b.iter(|| {
+            log_backend::format_plain(
+                LogLevel::Info,
+                black_box("my_crate::net"),
+                &black_box(MESSAGE),
+                &Theme::PLAIN,
+            )
+        })
+    });
+    group.bench_function("plain_dark", |b| {
+        let theme = Theme::dark();
+        b.iter(|| {
+            log_backend::format_plain(
+                LogLevel::Info,
+                black_box("my_crate::net"),
+                &black_box(MESSAGE),
+                &theme,
+            )
+        })
+    });
+    group.bench_function("env_logger", |b| {
+        let time = SystemTime::now();
+        b.iter(|| {
+            log_backend::format_env_logger(
+                time,
+                log::Level::Info,
+                black_box("my_crate::net"),
+                &black_box(MESSAGE),
+                &Theme::PLAIN,
+            )
+        })
+    });
+
+    group.bench_function("json", |b| {
+        let time = SystemTime::now();
+        b.iter(|| {
+            log_backend::format_json(
+                time,
+                log::Level::Info,
+                black_box("my_crate::net"),
+                black_box(MESSAGE),
+            )
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Elements(1));
+
+    // The console is left out, printing millions of records would bury the bench report
+
+    let dir = bench_dir("write");
+    let options = LogOptions {
+        file_path: dir.join("log.txt"),
+        ..LogOptions::default()
+    };
+    group.bench_function("file", |b| {
+        b.iter(|| {
+            task_1::write_to_log_with(&options, LogType::FileSystem, LogLevel::Info, MESSAGE)
+                .expect("Failed to write to log")
+        })
+    });
+
+    let synced = LogOptions {
+        file_path: dir.join("synced.txt"),
+        sync_policy: task_1::SyncPolicy::PerRecord,
+        ..LogOptions::default()
+    };
+    group.bench_function("file_synced", |b| {
+        b.iter(|| {
+            task_1::write_to_log_with(&synced, LogType::FileSystem, LogLevel::Info, MESSAGE)
+                .expect("Failed to write to log")
+        })
+    });
+
+    let batch = [MESSAGE; 100];
+    group.throughput(Throughput::Elements(batch.len() as u64));
+    group.bench_function("file_batch", |b| {
+        b.iter(|| {
+            task_1::write_all_to_log_with(&options, LogType::FileSystem, LogLevel::Info, batch)
+                .expect("Failed to write to log")
+        })
+    });
+    group.throughput(Throughput::Elements(1));
+
+    // The log crate macros through the installed backend, the logger can only be set once
+    let backend = LogBackend::new(log::LevelFilter::Info, LogType::FileSystem)
+        .with_format(Format::EnvLogger)
+        .with_options(LogOptions {
+            file_path: dir.join("backend.txt"),
+            ..LogOptions::default()
+        });
+    log_backend::install(backend).expect("Failed to install the log backend");
+    group.bench_function("backend", |b| {
+        b.iter(|| log::info!("{}", black_box(MESSAGE)))
+    });
+    group.bench_function("backend_filtered", |b| {
+        b.iter(|| log::debug!("{}", black_box(MESSAGE)))
+    });
+
+    group.finish();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// How write_all_to_log_with writes a batch, one buffer and one write call, against one vectored
+// write with an IoSlice per record. The record buffers are reused for both, so the vectored
+// write isn't charged for allocating them.
+fn bench_batch_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_write");
+    let batch = [MESSAGE; 100];
+    group.throughput(Throughput::Elements(batch.len() as u64));
+
+    let dir = bench_dir("batch_write");
+    let options = LogOptions::default();
+    let open = |name: &str| {
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(dir.join(name))
+            .expect("Failed to open the bench file")
+    };
+
+    let mut file = open("contiguous.txt");
+    let mut buffer = String::new();
+    group.bench_function("contiguous", |b| {
+        b.iter(|| {
+            buffer.clear();
+            for message in black_box(batch) {
+                task_1::format_record_into(&options, &mut buffer, LogLevel::Info, message);
+                buffer.push('\n');
+            }
+            file.write_all(buffer.as_bytes())
+                .expect("Failed to write the batch")
+        })
+    });
+
+    let mut file = open("vectored.txt");
+    let mut records = vec![String::new(); batch.len()];
+    group.bench_function("vectored", |b| {
+        b.iter(|| {
+            for (record, message) in records.iter_mut().zip(black_box(batch)) {
+                record.clear();
+                task_1::format_record_into(&options, record, LogLevel::Info, message);
+                record.push('\n');
+            }
+            let mut slices: Vec<IoSlice> = records
+                .iter()
+                .map(|record| IoSlice::new(record.as_bytes()))
+                .collect();
+            let mut slices = &mut slices[..];
+            while !slices.is_empty() {
+                let written = file
+                    .write_vectored(slices)
+                    .expect("Failed to write the batch");
+                IoSlice::advance_slices(&mut slices, written);
+            }
+        })
+    });
+
+    group.finish();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, bench_format, bench_write, bench_batch_write);
+criterion_main!(benches);
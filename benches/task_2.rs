@@ -0,0 +1,62 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use nxlog_task::task_2;
+
+const SIZES: [usize; 3] = [100, 10_000, 1_000_000];
+
+fn input(size: usize) -> Vec<String> {
+    (0..size)
+        .map(|i| {
+            if i % 4 == 0 {
+                String::new()
+            } else {
+                i.to_string()
+            }
+        })
+        .collect()
+}
+
+fn bench_foo(c: &mut Criterion) {
+    let mut group = c.benchmark_group("foo");
+
+    for size in SIZES {
+        let input = input(size);
+
+        // v1, v2 and foo_vec consume the Vec, so every iteration pays for a clone of the input
+        group.bench_with_input(BenchmarkId::new("v1", size), &input, |b, input| {
+            b.iter_batched(|| input.clone(), task_2::v1::foo, BatchSize::LargeInput)
+        });
+        group.bench_with_input(BenchmarkId::new("v2", size), &input, |b, input| {
+            b.iter_batched(|| input.clone(), task_2::v2::foo, BatchSize::LargeInput)
+        });
+        group.bench_with_input(BenchmarkId::new("iter", size), &input, |b, input| {
+            b.iter_batched(|| input.clone(), task_2::foo, BatchSize::LargeInput)
+        });
+
+        // Borrowed input, no clones of the values
+        group.bench_with_input(
+            BenchmarkId::new("iter_borrowed", size),
+            &input,
+            |b, input| b.iter(|| task_2::foo(black_box(input).iter())),
+        );
+
+        // Borrowed input and a reused output buffer
+        group.bench_with_input(BenchmarkId::new("into_reused", size), &input, |b, input| {
+            let mut output = Vec::with_capacity(input.len());
+            b.iter(|| {
+                task_2::foo_into(black_box(input).iter(), &mut output);
+                output.len()
+            })
+        });
+
+        #[cfg(feature = "rayon")]
+        group.bench_with_input(BenchmarkId::new("par", size), &input, |b, input| {
+            use rayon::prelude::*;
+            b.iter(|| task_2::par_foo(black_box(input).par_iter()))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_foo);
+criterion_main!(benches);
@@ -0,0 +1,75 @@
+// Throughput stress test of the file log type: several threads append to the same file
+// and the records/sec and the mean per-record latency are reported.
+//
+// cargo run --release --example log_stress -- [threads] [records per thread] [file]
+
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nxlog_task::task_1::{self, LogLevel, LogOptions, LogType};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let threads: usize = args
+        .next()
+        .map_or(4, |arg| arg.parse().expect("Invalid thread count"));
+    let records: usize = args
+        .next()
+        .map_or(100_000, |arg| arg.parse().expect("Invalid record count"));
+    let file_path = args.next().map_or_else(
+        || std::env::temp_dir().join("nxlog_task_stress.txt"),
+        PathBuf::from,
+    );
+
+    let _ = std::fs::remove_file(&file_path);
+    let options = LogOptions {
+        file_path: file_path.clone(),
+        ..LogOptions::default()
+    };
+
+    let started = Instant::now();
+    let latencies: Vec<Duration> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|thread| {
+                let options = &options;
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    for i in 0..records {
+                        let message = format!("stress thread {} record {}", thread, i);
+                        task_1::write_to_log_with(
+                            options,
+                            LogType::FileSystem,
+                            LogLevel::Info,
+                            message,
+                        )
+                        .expect("Failed to write to log");
+                    }
+                    started.elapsed()
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Worker thread panicked"))
+            .collect()
+    });
+    let elapsed = started.elapsed();
+
+    let total = (threads * records) as f64;
+    let busy: Duration = latencies.iter().sum();
+    println!("file:        {}", file_path.display());
+    println!(
+        "records:     {} ({} threads x {})",
+        threads * records,
+        threads,
+        records
+    );
+    println!("elapsed:     {:.3?}", elapsed);
+    println!(
+        "throughput:  {:.0} records/sec",
+        total / elapsed.as_secs_f64()
+    );
+    println!("latency:     {:.3?} per record", busy.div_f64(total));
+}
@@ -0,0 +1,24 @@
+// Verifies log files written with LogOptions::hash_chain, exits with 1 if any of them is broken.
+//
+// cargo run --example verify_hash_chain -- log.txt log.txt.1
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use nxlog_task::task_1::hash_chain;
+
+fn main() -> ExitCode {
+    let mut status = ExitCode::SUCCESS;
+
+    for path in std::env::args().skip(1) {
+        match hash_chain::verify(Path::new(&path)) {
+            Ok(records) => println!("{}: {} records verified", path, records),
+            Err(e) => {
+                eprintln!("{}: verification failed: {}", path, e);
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    status
+}
@@ -1,5 +1,19 @@
-use std::fmt::Display;
-use std::io::Write;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Write as _};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, LazyLock, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use regex::Regex;
 
 // 1. What's wrong:
 
@@ -48,12 +62,14 @@ use std::io::Write;
 //       [Advantages] More options for logging.
 //       [Disadvantages] The codebase requires extra dependencies. Logging might become a resource demanded in terms of CPU or Network usage.
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogType {
     Console,
     FileSystem,
     Network,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogLevel {
     Info,
     Error,
@@ -72,118 +88,4416 @@ impl Display for LogLevel {
     }
 }
 
-#[derive(Debug)]
+/// The log crate has no direct counterpart for every level, Trace is folded into Debug.
+impl From<log::Level> for LogLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => LogLevel::Error,
+            log::Level::Warn => LogLevel::Warn,
+            log::Level::Info => LogLevel::Info,
+            log::Level::Debug | log::Level::Trace => LogLevel::Debug,
+        }
+    }
+}
+
+impl From<LogLevel> for log::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Debug => log::Level::Debug,
+        }
+    }
+}
+
+/// The failures of the log types, with the cause kept as the error source.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum LogError {
-    FileOpenError(String),
-    FileWriteError(String),
-    LogError(String),
+    /// Opening, writing, syncing, rotating or reading a log file failed, `path` is `None` for
+    /// readers that aren't files.
+    #[error(
+        "I/O error{}: {source}",
+        path.as_ref().map_or(String::new(), |path| format!(" on {}", path.display()))
+    )]
+    Io {
+        path: Option<PathBuf>,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A log file or a record doesn't have the expected format, e.g. a broken hash chain, a
+    /// missing signature or a malformed JSON record.
+    #[error("{0}")]
+    Format(String),
+    /// The [`LogOptions`] can't be used, see [`LogOptions::validate`].
+    #[error("invalid log options: {0}")]
+    Config(String),
+    /// The log type has no implementation yet, e.g. [`LogType::Network`].
+    #[error("the {0:?} log type isn't supported")]
+    Unsupported(LogType),
+}
+
+impl LogError {
+    /// Whether the same write can succeed if retried later, e.g. after an interrupted call, a
+    /// timeout or a full disk. Format and configuration problems, missing permissions or an
+    /// unsupported log type won't go away by themselves.
+    pub fn is_transient(&self) -> bool {
+        self.retry_after().is_some()
+    }
+
+    /// How long to wait before retrying the failed write, `None` if retrying is pointless.
+    ///
+    /// The delays are hints for the first retry, callers retrying more than once are expected to
+    /// back off from there.
+    pub fn retry_after(&self) -> Option<Duration> {
+        let LogError::Io { source, .. } = self else {
+            return None;
+        };
+
+        match source.kind() {
+            ErrorKind::Interrupted => Some(Duration::ZERO),
+            ErrorKind::WouldBlock | ErrorKind::ResourceBusy => Some(Duration::from_millis(10)),
+            ErrorKind::TimedOut => Some(Duration::from_millis(100)),
+            // Until rotation, retention or another process frees some space
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => Some(Duration::from_secs(1)),
+            _ => None,
+        }
+    }
+}
+
+// map_err adapter for the I/O errors on the file at `path`
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> LogError + Copy + '_ {
+    move |source| LogError::Io {
+        path: Some(path.to_path_buf()),
+        source,
+    }
 }
 
 const DEFAULT_LOG_FILE_NAME: &str = "log.txt";
 
+/// How a message containing newlines is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MultiLine {
+    /// Write the newlines as they are, continuation lines look like separate records.
+    Raw,
+    /// Indent continuation lines with 4 spaces, records always start at the beginning of a line.
+    #[default]
+    Indent,
+    /// Replace the newlines with `\n`, every record stays on a single line.
+    Escape,
+    /// Write multi-line messages between a header and a footer line, e.g.
+    /// `[INFO] >>>`, the message lines, `[INFO] <<<`.
+    Frame,
+}
+
+impl MultiLine {
+    /// Joins the record `header` (e.g. `[INFO]`) and the `message` into the written text.
+    pub fn join(self, header: &str, message: &str) -> String {
+        let mut joined = String::with_capacity(header.len() + message.len() + 1);
+        self.join_into(&mut joined, &header, message);
+        joined
+    }
+
+    /// Same as [`MultiLine::join`], but appends the text to `out`, so the buffer can be reused.
+    pub fn join_into(self, out: &mut String, header: &dyn Display, message: &str) {
+        // Writing to a String never fails
+        let _ = match self {
+            _ if !message.contains('\n') => write!(out, "{} {}", header, message),
+            MultiLine::Raw => write!(out, "{} {}", header, message),
+            MultiLine::Indent => {
+                let _ = write!(out, "{} ", header);
+                push_lines(out, message, "\n    ");
+                Ok(())
+            }
+            MultiLine::Escape => {
+                let _ = write!(out, "{} ", header);
+                push_lines(out, message, "\\n");
+                Ok(())
+            }
+            MultiLine::Frame => {
+                let _ = writeln!(out, "{} >>>", header);
+                push_lines(out, message, "\n");
+                write!(out, "\n{} <<<", header)
+            }
+        };
+    }
+}
+
+// Pushes the lines of `message` separated by `separator`, i.e. every `\n` or `\r\n` is replaced.
+// Unlike lines(), a trailing newline is kept as an empty last line.
+fn push_lines(out: &mut String, message: &str, separator: &str) {
+    let mut rest = message;
+    while let Some((line, next)) = rest.split_once('\n') {
+        out.push_str(line.strip_suffix('\r').unwrap_or(line));
+        out.push_str(separator);
+        rest = next;
+    }
+    out.push_str(rest);
+}
+
+// The Unicode bidi embeddings, overrides and isolates, which can make a line read differently
+// from how it's stored
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Escapes control characters (including ESC, so ANSI escape sequences are neutralized)
+/// and Unicode bidi overrides in a user-supplied message, e.g. `\x1b` becomes `\u{1b}`.
+///
+/// Newlines, including `\r\n`, are kept and left to [`MultiLine`].
+pub fn escape_control_chars(message: &str) -> Cow<'_, str> {
+    let needs_escape = |c: char, next: Option<&char>| {
+        let newline = c == '\n' || (c == '\r' && next == Some(&'\n'));
+        (c.is_control() || is_bidi_control(c)) && !newline
+    };
+
+    // Allocates only once the first character to escape is found
+    let mut escaped: Option<String> = None;
+    let mut chars = message.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if needs_escape(c, chars.peek().map(|(_, next)| next)) {
+            escaped
+                .get_or_insert_with(|| message[..i].to_string())
+                .extend(c.escape_unicode());
+        } else if let Some(escaped) = &mut escaped {
+            escaped.push(c);
+        }
+    }
+
+    escaped.map_or(Cow::Borrowed(message), Cow::Owned)
+}
+
+/// An anonymization transform of [`LogOptions::anonymize`], so personal data never reaches the
+/// log types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anonymize {
+    /// Replace IPv4 and IPv6 addresses with `ip:<hash>`, a SHA-256 of the salt and the address.
+    /// The same address always gets the same hash, so the records can still be correlated.
+    /// Keep the salt secret, IPv4 hashes are easy to brute-force otherwise.
+    HashIpAddresses { salt: String },
+    /// Mask the local part of email addresses but its first character, `j***@example.com`.
+    MaskEmails,
+    /// Remove the `key=value` (or `key="quoted value"`) fields with these keys.
+    DropKeys(Vec<String>),
+}
+
+/// Applies the `transforms` to a message in order, e.g. `user=john@example.com` becomes
+/// `user=j***@example.com` with [`Anonymize::MaskEmails`].
+pub fn anonymize<'a>(message: &'a str, transforms: &[Anonymize]) -> Cow<'a, str> {
+    static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"\b([A-Za-z0-9._%+-])[A-Za-z0-9._%+-]*@([A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)+)")
+            .unwrap()
+    });
+    // None if the transform changed nothing
+    let owned = |transformed: Cow<'_, str>| match transformed {
+        Cow::Owned(transformed) => Some(transformed),
+        Cow::Borrowed(_) => None,
+    };
+
+    let mut message = Cow::Borrowed(message);
+    for transform in transforms {
+        let transformed = match transform {
+            Anonymize::HashIpAddresses { salt } => hash_ip_addresses(&message, salt),
+            Anonymize::MaskEmails => owned(EMAIL.replace_all(&message, "$1***@$2")),
+            Anonymize::DropKeys(keys) => drop_keys(&message, keys),
+        };
+        if let Some(transformed) = transformed {
+            message = Cow::Owned(transformed);
+        }
+    }
+
+    message
+}
+
+// Replaces the IP addresses with their hashes, None if none found
+fn hash_ip_addresses(message: &str, salt: &str) -> Option<String> {
+    // Candidates only, the matches that don't parse as an address are left as they are. An IPv6
+    // candidate starts after a non-hex character, so the `:` of `key:fe80::1` isn't part of
+    // it, and stops at `]` or `%`, i.e. `[::1]:80` and `fe80::1%eth0` keep the port and zone.
+    static IP_ADDRESS: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"\b(\d{1,3}(?:\.\d{1,3}){3})\b|(?:^|[^0-9A-Fa-f])((?:[0-9A-Fa-f]{1,4}:|::)[0-9A-Fa-f:.]*)",
+        )
+        .unwrap()
+    });
+
+    let mut hashed = String::new();
+    let mut copied = 0;
+    for captures in IP_ADDRESS.captures_iter(message) {
+        let (start, address, parsed) = match (captures.get(1), captures.get(2)) {
+            (Some(v4), _) => {
+                let address = v4.as_str();
+                (
+                    v4.start(),
+                    address,
+                    address.parse::<Ipv4Addr>().map(IpAddr::from),
+                )
+            }
+            (_, Some(v6)) => {
+                // A sentence may end right after the address
+                let address = v6.as_str().trim_end_matches('.');
+                (
+                    v6.start(),
+                    address,
+                    address.parse::<Ipv6Addr>().map(IpAddr::from),
+                )
+            }
+            _ => continue,
+        };
+        if let Ok(parsed) = parsed {
+            hashed.push_str(&message[copied..start]);
+            hashed.push_str(&hash_ip_address(salt, parsed));
+            copied = start + address.len();
+        }
+    }
+
+    (copied > 0).then(|| hashed + &message[copied..])
+}
+
+fn hash_ip_address(salt: &str, address: IpAddr) -> String {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(address.to_string().as_bytes());
+
+    let mut hashed = String::from("ip:");
+    push_hex(&mut hashed, &hasher.finalize()[..8]);
+    hashed
+}
+
+// Removes the `key=value` and `key="quoted value"` fields with one of the keys, None if none found
+fn drop_keys(message: &str, keys: &[String]) -> Option<String> {
+    let mut kept = String::with_capacity(message.len());
+    let mut dropped = false;
+    let mut rest = message;
+
+    while !rest.is_empty() {
+        let field_len = if rest.starts_with(char::is_whitespace) {
+            rest.find(|c: char| !c.is_whitespace())
+                .unwrap_or(rest.len())
+        } else {
+            field_len(rest)
+        };
+        let (field, next) = rest.split_at(field_len);
+
+        let is_dropped = keys.iter().any(|key| {
+            field
+                .strip_prefix(key.as_str())
+                .is_some_and(|field| field.starts_with('='))
+        });
+        if is_dropped {
+            // The whitespace before the field goes too
+            kept.truncate(kept.trim_end().len());
+            dropped = true;
+        } else if !(dropped && kept.is_empty() && field.trim().is_empty()) {
+            // Whitespace after a dropped first field is skipped instead
+            kept.push_str(field);
+        }
+        rest = next;
+    }
+
+    dropped.then_some(kept)
+}
+
+// Length of the field at the start of `text`, up to the next whitespace out of double quotes
+fn field_len(text: &str) -> usize {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return i,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// What the file sink does when the next record would grow the file past the cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFull {
+    /// Stop logging to the file, the records are dropped.
+    Stop,
+    /// Truncate the file and continue writing from the start.
+    Overwrite,
+    /// Rename the file to `<file>.1` (shifting `<file>.1` to `<file>.2`, ...),
+    /// keeping at most `keep` rotated files and deleting the oldest one.
+    Rotate { keep: usize },
+}
+
+/// A hard cap on the size of the log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSizeCap {
+    pub max_bytes: u64,
+    pub on_full: OnFull,
+}
+
+/// What the file sink does when the free disk space drops below the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnLowDiskSpace {
+    /// Drop Debug and Info records, keep writing Warn and Error.
+    DropVerbose,
+    /// Keep writing, but write a Warn record about the low disk space first,
+    /// once until the free space recovers.
+    Warn,
+    /// Delete the rotated files (`<file>.N`), oldest first, until there is enough space again.
+    PurgeRotated,
+}
+
+/// A free disk space threshold for the file sink, checked before every write.
+///
+/// Enforced on Unix, where the free space is read with `statvfs`, and on Windows, with
+/// `GetDiskFreeSpaceExW`. Never low on other targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpaceGuard {
+    pub min_free_bytes: u64,
+    pub on_low: OnLowDiskSpace,
+}
+
+/// When the file sink calls fsync, trading throughput for durability on power loss.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Leave it to the OS.
+    #[default]
+    Never,
+    /// After every batch of records, a single-record write is a batch of one.
+    PerBatch,
+    /// After every record.
+    PerRecord,
+    /// After every Error record, which also persists all the records written before it.
+    PerError,
+}
+
+impl SyncPolicy {
+    fn syncs_after(self, log_level: LogLevel, end_of_batch: bool) -> bool {
+        match self {
+            SyncPolicy::Never => false,
+            SyncPolicy::PerBatch => end_of_batch,
+            SyncPolicy::PerRecord => true,
+            SyncPolicy::PerError => log_level == LogLevel::Error,
+        }
+    }
+}
+
+/// The owner assigned to newly created log files and directories on Unix, e.g. when the
+/// process creates them as root before dropping privileges. `None` keeps the current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOwner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+#[cfg(unix)]
+impl FileOwner {
+    /// Resolves the user and group names, e.g. `FileOwner::from_names(Some("syslog"), Some("adm"))`.
+    pub fn from_names(user: Option<&str>, group: Option<&str>) -> std::io::Result<Self> {
+        Ok(Self {
+            uid: user.map(user_id).transpose()?,
+            gid: group.map(group_id).transpose()?,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn user_id(name: &str) -> std::io::Result<u32> {
+    let c_name = c_name(name)?;
+    lookup_id(name, |buffer, result| {
+        let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+        let mut found = std::ptr::null_mut();
+        // SAFETY: all the pointers are valid for the call, `passwd` is read only if it was found
+        let code = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            )
+        };
+        *result = (!found.is_null()).then(|| unsafe { passwd.assume_init() }.pw_uid);
+        code
+    })
+}
+
+#[cfg(unix)]
+fn group_id(name: &str) -> std::io::Result<u32> {
+    let c_name = c_name(name)?;
+    lookup_id(name, |buffer, result| {
+        let mut group = std::mem::MaybeUninit::<libc::group>::uninit();
+        let mut found = std::ptr::null_mut();
+        // SAFETY: all the pointers are valid for the call, `group` is read only if it was found
+        let code = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                group.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            )
+        };
+        *result = (!found.is_null()).then(|| unsafe { group.assume_init() }.gr_gid);
+        code
+    })
+}
+
+#[cfg(unix)]
+fn c_name(name: &str) -> std::io::Result<std::ffi::CString> {
+    std::ffi::CString::new(name).map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))
+}
+
+// Calls a getXXnam_r-style `lookup`, growing the buffer while it reports ERANGE
+#[cfg(unix)]
+fn lookup_id<F>(name: &str, mut lookup: F) -> std::io::Result<u32>
+where
+    F: FnMut(&mut Vec<libc::c_char>, &mut Option<u32>) -> libc::c_int,
+{
+    let mut buffer = vec![0; 1024];
+    loop {
+        let mut result = None;
+        match lookup(&mut buffer, &mut result) {
+            0 => {
+                return result.ok_or_else(|| {
+                    std::io::Error::new(ErrorKind::NotFound, format!("{:?} is not found", name))
+                })
+            }
+            libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+            code => return Err(std::io::Error::from_raw_os_error(code)),
+        }
+    }
+}
+
+/// Options of [`write_to_log_with`].
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// The file written by [`LogType::FileSystem`], `log.txt` by default.
+    pub file_path: PathBuf,
+    /// The size cap of the log file, unlimited by default.
+    /// A record larger than the cap itself is never written to the file.
+    pub max_file_size: Option<FileSizeCap>,
+    /// Retention bound on the cumulative size of the log file and its rotated files,
+    /// unlimited by default. Checked on every [`OnFull::Rotate`] rotation: the oldest
+    /// rotated files are deleted until they fit together with a full-size current file.
+    pub max_total_size: Option<u64>,
+    /// The degraded mode entered when the disk is almost full, disabled by default.
+    pub disk_space_guard: Option<DiskSpaceGuard>,
+    /// When the log file is synced to the disk, never by default.
+    pub sync_policy: SyncPolicy,
+    /// Unix mode of newly created log files, e.g. `0o600`. The process umask still applies,
+    /// existing files keep their mode. `None` uses the default `0o666` (minus umask).
+    pub file_mode: Option<u32>,
+    /// Unix mode of newly created parent directories of the log file, e.g. `0o700`.
+    pub dir_mode: Option<u32>,
+    /// Unix owner of newly created log files and parent directories, unchanged by default.
+    pub owner: Option<FileOwner>,
+    /// How messages containing newlines are written.
+    pub multi_line: MultiLine,
+    /// Escape control characters in messages, see [`escape_control_chars`]. Enabled by default,
+    /// so attacker-controlled strings can't forge log lines or send escape codes to terminals.
+    pub escape_control_chars: bool,
+    /// Anonymization applied to every message before it's written, see [`anonymize`]. None by
+    /// default.
+    pub anonymize: Vec<Anonymize>,
+    /// End every record of the file log type with the hash of the chain, ` #<sha-256>`, so
+    /// modified or removed records can be detected with [`hash_chain::verify`]. A JSON object
+    /// record ([`log_backend::Format::Json`]) gets a last `"hash"` field instead, so the file
+    /// can still be read with [`log_backend::read_json_lines`]. Disabled by default.
+    pub hash_chain: bool,
+    /// Sign every file rotated away by [`OnFull::Rotate`] with this key, see [`signature`].
+    /// None by default.
+    pub signing_key: Option<signature::SigningKey>,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            file_path: PathBuf::from(DEFAULT_LOG_FILE_NAME),
+            max_file_size: None,
+            max_total_size: None,
+            disk_space_guard: None,
+            sync_policy: SyncPolicy::default(),
+            file_mode: None,
+            dir_mode: None,
+            owner: None,
+            multi_line: MultiLine::default(),
+            escape_control_chars: true,
+            anonymize: Vec::new(),
+            hash_chain: false,
+            signing_key: None,
+        }
+    }
+}
+
+impl LogOptions {
+    /// Checks that the options make sense together, the error names the offending field, e.g.
+    /// `max_total_size (40) is smaller than max_file_size.max_bytes (100)`.
+    ///
+    /// The options are used as they are otherwise, so call it where they are built from a
+    /// configuration.
+    pub fn validate(&self) -> Result<(), LogError> {
+        let invalid = |message: String| Err(LogError::Config(message));
+        let rotates = matches!(
+            self.max_file_size,
+            Some(FileSizeCap {
+                on_full: OnFull::Rotate { keep },
+                ..
+            }) if keep > 0
+        );
+
+        if self.file_path.file_name().is_none() {
+            return invalid(format!("file_path ({:?}) has no file name", self.file_path));
+        }
+        if let Some(cap) = self.max_file_size {
+            if cap.max_bytes == 0 {
+                return invalid("max_file_size.max_bytes is 0, no record would fit".to_string());
+            }
+        }
+        if let Some(max_total_size) = self.max_total_size {
+            match self.max_file_size {
+                Some(cap) if rotates && max_total_size < cap.max_bytes => {
+                    return invalid(format!(
+                        "max_total_size ({}) is smaller than max_file_size.max_bytes ({})",
+                        max_total_size, cap.max_bytes
+                    ));
+                }
+                _ if !rotates => {
+                    return invalid(
+                        "max_total_size requires max_file_size.on_full to be OnFull::Rotate \
+                         with keep > 0"
+                            .to_string(),
+                    );
+                }
+                _ => {}
+            }
+        }
+        for (field, mode) in [("file_mode", self.file_mode), ("dir_mode", self.dir_mode)] {
+            if let Some(mode) = mode.filter(|mode| *mode > 0o7777) {
+                return invalid(format!("{} ({:#o}) is not a file mode", field, mode));
+            }
+        }
+        if let Some(mode) = self.dir_mode.filter(|mode| mode & 0o100 == 0) {
+            return invalid(format!(
+                "dir_mode ({:#o}) lacks the owner's execute bit, the log file couldn't be created",
+                mode
+            ));
+        }
+        if self.signing_key.is_some() && !rotates {
+            return invalid(
+                "signing_key requires max_file_size.on_full to be OnFull::Rotate with keep > 0"
+                    .to_string(),
+            );
+        }
+        for transform in &self.anonymize {
+            if matches!(transform, Anonymize::HashIpAddresses { salt } if salt.is_empty()) {
+                return invalid("anonymize: the salt of HashIpAddresses is empty".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Applies the scoped prefixes and the anonymization to a message
+    fn transform<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        match with_scoped_prefixes(message) {
+            Cow::Borrowed(message) => anonymize(message, &self.anonymize),
+            Cow::Owned(prefixed) => Cow::Owned(anonymize(&prefixed, &self.anonymize).into_owned()),
+        }
+    }
+
+    // Applies the scoped prefixes and the options to a message and appends it to `out`,
+    // after the record header
+    fn join_into(&self, out: &mut String, header: &dyn Display, message: &str) {
+        let message = self.transform(message);
+
+        if self.escape_control_chars {
+            self.multi_line
+                .join_into(out, header, &escape_control_chars(&message))
+        } else {
+            self.multi_line.join_into(out, header, &message)
+        }
+    }
+}
+
+/// Formats a record the same way [`write_to_log_with`] does, e.g. `[INFO] message`, and
+/// appends it to `out`, without the trailing newline.
+///
+/// Nothing is allocated once `out` is large enough (escaping and scoped prefixes only allocate
+/// when there is something to escape or prefix), so a custom sink can reuse one buffer for all
+/// of its records, see [`with_record_buffer`].
+pub fn format_record_into(
+    options: &LogOptions,
+    out: &mut String,
+    log_level: LogLevel,
+    message: &str,
+) {
+    options.join_into(out, &format_args!("[{}]", log_level), message)
+}
+
+thread_local! {
+    static RECORD_BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// Larger buffers are shrunk after use, so a single huge record doesn't pin its memory
+const MAX_RETAINED_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// Calls `f` with an empty buffer owned by the current thread and reused by every call,
+/// the one the log types are written from.
+///
+/// A nested call (e.g. from a `Display` implementation that logs) gets a new buffer instead.
+pub fn with_record_buffer<R>(f: impl FnOnce(&mut String) -> R) -> R {
+    with_buffer(&RECORD_BUFFER, f)
+}
+
+fn with_buffer<R>(
+    buffer: &'static std::thread::LocalKey<RefCell<String>>,
+    f: impl FnOnce(&mut String) -> R,
+) -> R {
+    buffer.with(|buffer| match buffer.try_borrow_mut() {
+        Ok(mut buffer) => {
+            buffer.clear();
+            let result = f(&mut buffer);
+            buffer.clear();
+            buffer.shrink_to(MAX_RETAINED_BUFFER_CAPACITY);
+            result
+        }
+        Err(_) => f(&mut String::new()),
+    })
+}
+
+thread_local! {
+    static SCOPED_PREFIXES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Guard returned by [`scoped_prefix`], removes the prefix when dropped.
+#[must_use = "the prefix is removed as soon as the guard is dropped"]
+pub struct ScopedPrefix {
+    // The prefixes are per thread, so the guard must be dropped on the thread that created it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ScopedPrefix {
+    fn drop(&mut self) {
+        SCOPED_PREFIXES.with(|prefixes| prefixes.borrow_mut().pop());
+    }
+}
+
+/// Prefixes every message written by the current thread with `[prefix]` until the returned
+/// guard is dropped, e.g. `let _g = scoped_prefix("worker-3");`.
+///
+/// Nested scopes stack up: `[worker-3] [job-7] message`. The guards are expected to be dropped
+/// in the reverse order of creation, which is what scopes do.
+pub fn scoped_prefix(prefix: impl Into<String>) -> ScopedPrefix {
+    SCOPED_PREFIXES.with(|prefixes| prefixes.borrow_mut().push(prefix.into()));
+    ScopedPrefix {
+        _not_send: PhantomData,
+    }
+}
+
+fn with_scoped_prefixes(message: &str) -> Cow<'_, str> {
+    SCOPED_PREFIXES.with(|prefixes| {
+        let prefixes = prefixes.borrow();
+        if prefixes.is_empty() {
+            return Cow::Borrowed(message);
+        }
+
+        let mut prefixed = String::new();
+        for prefix in prefixes.iter() {
+            prefixed.push('[');
+            prefixed.push_str(prefix);
+            prefixed.push_str("] ");
+        }
+        prefixed.push_str(message);
+        Cow::Owned(prefixed)
+    })
+}
+
+/// What a successful write did with the record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Written {
+    /// Bytes handed to the log type, including the newline and the hash of the chain, 0 if the
+    /// record was dropped.
+    pub bytes: usize,
+    /// The record, or a record of a batch, was dropped on purpose instead of written, see
+    /// [`dropped_records`].
+    pub dropped: bool,
+    /// The file has been synced to the storage device after the record, see [`SyncPolicy`].
+    /// Otherwise the record may still be in the OS page cache, or in the stdout buffer.
+    pub synced: bool,
+}
+
 /// Writes a log message to a log_type target, filtered by a log_level.
 ///
-/// Returns `Ok(())` on success, otherwise returns LogError.
+/// Returns a [`Written`] receipt on success, otherwise returns LogError. In release builds, the
+/// levels compiled out by a `release_max_level_*` feature write nothing and return an empty
+/// receipt.
 ///
 /// # Arguments
 ///
 /// * `log_type` - a log target to accept the log message.
 ///
 /// * `log_level` - a log level to filter the log message.
-pub fn write_to_log<T>(log_type: LogType, log_level: LogLevel, value: T) -> Result<(), LogError>
+pub fn write_to_log<T>(
+    log_type: LogType,
+    log_level: LogLevel,
+    value: T,
+) -> Result<Written, LogError>
+where
+    T: AsRef<str>,
+{
+    write_to_log_with(&LogOptions::default(), log_type, log_level, value)
+}
+
+// The level of the most restrictive release_max_level_* feature, so that enabling several of
+// them (e.g. with --all-features) still builds
+const RELEASE_MAX_LEVEL: log::LevelFilter = if cfg!(feature = "release_max_level_off") {
+    log::LevelFilter::Off
+} else if cfg!(feature = "release_max_level_error") {
+    log::LevelFilter::Error
+} else if cfg!(feature = "release_max_level_warn") {
+    log::LevelFilter::Warn
+} else if cfg!(feature = "release_max_level_info") {
+    log::LevelFilter::Info
+} else if cfg!(feature = "release_max_level_debug") {
+    log::LevelFilter::Debug
+} else {
+    log::LevelFilter::Trace
+};
+
+// False for the levels compiled out by the release_max_level_* features (or by the log crate
+// features of the binary), the check is a constant once a call with a literal level is inlined
+#[inline(always)]
+fn statically_enabled(log_level: LogLevel) -> bool {
+    let level = log::Level::from(log_level);
+    level <= log::STATIC_MAX_LEVEL && (cfg!(debug_assertions) || level <= RELEASE_MAX_LEVEL)
+}
+
+/// Same as [`write_to_log`], but with explicit `options` instead of the defaults.
+pub fn write_to_log_with<T>(
+    options: &LogOptions,
+    log_type: LogType,
+    log_level: LogLevel,
+    value: T,
+) -> Result<Written, LogError>
 where
     T: AsRef<str>,
 {
-    let log_message = format!("[{}] {}", log_level, value.as_ref());
+    if !statically_enabled(log_level) {
+        return Ok(Written::default());
+    }
+
+    with_record_buffer(|line| {
+        format_record_into(options, line, log_level, value.as_ref());
+        line.push('\n');
+        write_line(options, log_type, log_level, line)
+    })
+}
+
+/// Writes every value of `values` as a record of `log_level` to a log_type target, see
+/// [`write_to_log`].
+pub fn write_all_to_log<I>(
+    log_type: LogType,
+    log_level: LogLevel,
+    values: I,
+) -> Result<Written, LogError>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    write_all_to_log_with(&LogOptions::default(), log_type, log_level, values)
+}
+
+/// Same as [`write_all_to_log`], but with explicit `options` instead of the defaults.
+///
+/// The records are formatted into one buffer and written with a single write call and at most
+/// one sync, the end of the batch for [`SyncPolicy::PerBatch`]. With a size cap, a disk space
+/// guard or a hash chain, the file is checked and appended per record instead, so each record
+/// is capped, rotated or chained the same way it would be on its own.
+pub fn write_all_to_log_with<I>(
+    options: &LogOptions,
+    log_type: LogType,
+    log_level: LogLevel,
+    values: I,
+) -> Result<Written, LogError>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    if !statically_enabled(log_level) {
+        return Ok(Written::default());
+    }
 
+    with_record_buffer(|buffer| {
+        let per_record = log_type == LogType::FileSystem
+            && (options.max_file_size.is_some()
+                || options.disk_space_guard.is_some()
+                || options.hash_chain);
+        if !per_record {
+            for value in values {
+                format_record_into(options, buffer, log_level, value.as_ref());
+                buffer.push('\n');
+            }
+            if buffer.is_empty() {
+                return Ok(Written::default());
+            }
+            return write_line(options, log_type, log_level, buffer);
+        }
+
+        let mut written = Written::default();
+        for value in values {
+            buffer.clear();
+            format_record_into(options, buffer, log_level, value.as_ref());
+            buffer.push('\n');
+
+            let record = write_file(options, log_level, buffer, false)?;
+            written.bytes += record.bytes;
+            written.dropped |= record.dropped;
+            written.synced |= record.synced;
+        }
+
+        // Not with the last record, which may have been dropped
+        if written.bytes > 0 && options.sync_policy == SyncPolicy::PerBatch {
+            let path = platform_path(&options.file_path);
+            open_log_file(options, &path)?
+                .sync_data()
+                .map_err(io_error(&path))?;
+            written.synced = true;
+        }
+        Ok(written)
+    })
+}
+
+// Writes an already formatted line, ending with a newline, to the log_type target
+fn write_line(
+    options: &LogOptions,
+    log_type: LogType,
+    log_level: LogLevel,
+    line: &str,
+) -> Result<Written, LogError> {
     match log_type {
-        LogType::Console => println!("{}", log_message),
-        LogType::FileSystem => {
-            // The file expects not to be inlined in the function, but exists outside and reused
-            let mut file = std::fs::OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(DEFAULT_LOG_FILE_NAME)
-                .map_err(|e| LogError::FileOpenError(e.to_string()))?;
-            writeln!(file, "{}", log_message)
-                .map_err(|e| LogError::FileWriteError(e.to_string()))?
+        LogType::Console => {
+            print!("{}", line);
+            Ok(Written {
+                bytes: line.len(),
+                ..Written::default()
+            })
         }
-        LogType::Network => todo!("Requires network implementation"),
+        LogType::FileSystem => write_file(options, log_level, line, true),
+        LogType::Network => Err(LogError::Unsupported(log_type)),
     }
-
-    Ok(())
 }
 
-mod external_log {
-    pub fn write_to_log<T>(value: T)
-    where
-        T: AsRef<str>,
-    {
-        log::info!("{}", value.as_ref());
+// `line` may hold several records when it's the whole batch
+fn write_file(
+    options: &LogOptions,
+    log_level: LogLevel,
+    line: &str,
+    end_of_batch: bool,
+) -> Result<Written, LogError> {
+    let path = platform_path(&options.file_path);
+    let path = path.as_ref();
+
+    let mut warning = Written::default();
+    if let Some(guard) = options.disk_space_guard {
+        match check_disk_space(path, guard, log_level)? {
+            DiskSpace::Enough => {}
+            DiskSpace::DropRecord => return drop_record(),
+            DiskSpace::WarnFirst => {
+                // Capped, chained and counted like any other record
+                let record = low_disk_space_warning(guard, line);
+                warning = append_capped(options, path, LogLevel::Warn, &record, false)?;
+            }
+        }
     }
+
+    let record = append_capped(options, path, log_level, line, end_of_batch)?;
+    Ok(Written {
+        bytes: warning.bytes + record.bytes,
+        dropped: warning.dropped || record.dropped,
+        // Whether the last write was synced
+        synced: if record.dropped {
+            warning.synced
+        } else {
+            record.synced
+        },
+    })
 }
 
-pub fn run() {
-    let s_slice = "String slice";
-    let s_owned = String::from("Owned String");
+// Appends `line` to the file at `path` within the size cap, rotating or dropping as configured
+fn append_capped(
+    options: &LogOptions,
+    path: &Path,
+    log_level: LogLevel,
+    line: &str,
+    end_of_batch: bool,
+) -> Result<Written, LogError> {
+    if let Some(cap) = options.max_file_size {
+        let line_len = line.len() as u64 + hash_chain::suffix_len(options, line);
+        let file_len = fs::metadata(path).map_or(0, |metadata| metadata.len());
 
-    if let Err(e) = write_to_log(
-        LogType::FileSystem,
-        LogLevel::Info,
-        "Another one string slice",
-    ) {
-        eprintln!("Logging failed with error: {:?}", e);
+        if file_len + line_len > cap.max_bytes {
+            if line_len > cap.max_bytes {
+                return drop_record();
+            }
+            match cap.on_full {
+                OnFull::Stop => return drop_record(),
+                OnFull::Overwrite => {
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .truncate(true)
+                        .open(path)
+                        .map_err(io_error(path))?;
+                }
+                OnFull::Rotate { keep } => {
+                    rotate(path, keep).map_err(io_error(path))?;
+                    match &options.signing_key {
+                        Some(key) if keep > 0 => signature::sign(&rotated_path(path, 1), key)?,
+                        _ => {}
+                    }
+                    // The new file can grow up to the cap until the next rotation
+                    if let Some(max_total_bytes) = options.max_total_size {
+                        enforce_retention(path, max_total_bytes.saturating_sub(cap.max_bytes))
+                            .map_err(io_error(path))?;
+                    }
+                }
+            }
+        }
     }
-    let _ = write_to_log(LogType::Console, LogLevel::Debug, &s_owned); // Just suppress error message
-    write_to_log(LogType::Console, LogLevel::Info, s_slice)
-        .expect("Non-recoverable error: Logging failed");
 
-    // Other ways to create a string in Rust. Will require more complex implementation of the write_to_log function
-    // let s_pathbuf = PathBuf::from("some/path");
-    // let s_path = Path::new("some/path");
-    // let s_vec_of_u8 = vec!['a', 'b', 'c', 'd', 'e'];
-    // let s_slice_of_u8 = &['a', 'b', 'c', 'd', 'e'];
-    // let s_osstring = OsString::from("some/path");
+    // The file expects not to be inlined in the function, but exists outside and reused
+    let mut file = open_log_file(options, path)?;
+    let bytes = append_record(options, path, &mut file, line)?;
 
-    external_log::write_to_log(s_slice);
-    external_log::write_to_log(s_owned);
-    external_log::write_to_log("string slice again");
+    let synced = options.sync_policy.syncs_after(log_level, end_of_batch);
+    if synced {
+        file.sync_data().map_err(io_error(path))?;
+    }
+
+    Ok(Written {
+        bytes,
+        dropped: false,
+        synced,
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use std::io::{BufRead, BufReader};
+/// Receives the failures of the logger itself, i.e. the ones there is no caller to return to.
+pub type InternalErrorSink = fn(&str);
 
-    #[test]
-    fn test_write_to_log_to_filesystem() {
-        let test_message = "Test log message";
-        let log_level = LogLevel::Info;
+static INTERNAL_ERROR_SINK: RwLock<InternalErrorSink> = RwLock::new(print_internal_error);
+static INTERNAL_ERROR_RATE: Mutex<InternalErrorRate> = Mutex::new(InternalErrorRate {
+    window_start: None,
+    reported: 0,
+    suppressed: 0,
+});
 
-        // 0. Remove the log file before the test
-        fs::remove_file(DEFAULT_LOG_FILE_NAME).expect("Failed to delete test log file");
+// At most this many internal errors are reported per second, a broken log file mustn't flood stderr
+const MAX_INTERNAL_ERRORS_PER_SEC: u32 = 10;
 
-        // 1. Create a message
-        let expected_output = format!("[{}] {}", log_level, test_message);
+thread_local! {
+    // Set while the sink runs, so a sink that logs and fails again can't recurse
+    static REPORTING_INTERNAL_ERROR: Cell<bool> = const { Cell::new(false) };
+}
 
-        // 2. Write to log using write_to_log function
-        write_to_log(LogType::FileSystem, log_level, test_message).expect("Failed to write to log");
+struct InternalErrorRate {
+    window_start: Option<Instant>,
+    reported: u32,
+    suppressed: u64,
+}
 
-        let file = fs::File::open(DEFAULT_LOG_FILE_NAME).expect("Failed to open log file");
-        let reader = BufReader::new(file);
+fn print_internal_error(message: &str) {
+    eprintln!("{}", message);
+}
 
-        // Here, we assume that the last line is tho only line in the fiile
-        let last_line = reader
-            .lines()
-            .last()
-            .expect("Log file is empty")
-            .expect("Failed to read from log file");
+/// Replaces the sink of the internal errors, stderr by default.
+///
+/// Internal errors are the write failures of the log backend and the heartbeat. They are rate
+/// limited, with a summary of the suppressed ones, and never reported from within the sink.
+pub fn set_internal_error_sink(sink: InternalErrorSink) {
+    *INTERNAL_ERROR_SINK
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = sink;
+}
 
-        // 3. Validate the content of the log file by comparing it with the message
-        assert_eq!(last_line, expected_output);
+fn report_internal_error(error: &LogError) {
+    report_internal_error_at(error, Instant::now())
+}
 
-        // Cleanup: remove the log file after the test
-        fs::remove_file(DEFAULT_LOG_FILE_NAME).expect("Failed to delete test log file");
+fn report_internal_error_at(error: &LogError, now: Instant) {
+    if REPORTING_INTERNAL_ERROR.with(|reporting| reporting.replace(true)) {
+        return;
+    }
+
+    let suppressed = {
+        let mut rate = INTERNAL_ERROR_RATE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let window_expired = rate
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= Duration::from_secs(1));
+        if window_expired {
+            rate.window_start = Some(now);
+            rate.reported = 0;
+        }
+
+        if rate.reported < MAX_INTERNAL_ERRORS_PER_SEC {
+            rate.reported += 1;
+            Some(std::mem::take(&mut rate.suppressed))
+        } else {
+            rate.suppressed += 1;
+            None
+        }
+    };
+
+    if let Some(suppressed) = suppressed {
+        let sink = *INTERNAL_ERROR_SINK
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if suppressed > 0 {
+            sink(&format!("{} more logging errors suppressed", suppressed));
+        }
+        sink(&format!("Logging failed with error: {}", error));
+    }
+
+    REPORTING_INTERNAL_ERROR.with(|reporting| reporting.set(false));
+}
+
+// Records dropped by the file log type since the start of the process
+static DROPPED_RECORDS: AtomicU64 = AtomicU64::new(0);
+
+// Counts a record that is dropped instead of written, which isn't an error
+fn drop_record() -> Result<Written, LogError> {
+    DROPPED_RECORDS.fetch_add(1, Ordering::Relaxed);
+    Ok(Written {
+        dropped: true,
+        ..Written::default()
+    })
+}
+
+/// Number of records dropped on purpose since the start of the process: by a full file with
+/// [`OnFull::Stop`], for being larger than the cap, or by [`OnLowDiskSpace::DropVerbose`].
+pub fn dropped_records() -> u64 {
+    DROPPED_RECORDS.load(Ordering::Relaxed)
+}
+
+// The log files the Warn record about the low disk space has been written to, until the space
+// recovers. Per file, a warning written to one log doesn't show up in the others.
+static LOW_DISK_SPACE_WARNED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+// What to do with a record, according to the disk space guard
+enum DiskSpace {
+    Enough,
+    DropRecord,
+    WarnFirst,
+}
+
+// Applies the degraded mode if the disk is almost full
+fn check_disk_space(
+    path: &Path,
+    guard: DiskSpaceGuard,
+    log_level: LogLevel,
+) -> Result<DiskSpace, LogError> {
+    let is_low = || available_space(path).is_some_and(|free| free < guard.min_free_bytes);
+    let warned = || {
+        LOW_DISK_SPACE_WARNED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    };
+
+    if !is_low() {
+        warned().remove(path);
+        return Ok(DiskSpace::Enough);
+    }
+
+    match guard.on_low {
+        OnLowDiskSpace::DropVerbose => match log_level {
+            LogLevel::Warn | LogLevel::Error => Ok(DiskSpace::Enough),
+            LogLevel::Debug | LogLevel::Info => Ok(DiskSpace::DropRecord),
+        },
+        OnLowDiskSpace::Warn if warned().insert(path.to_path_buf()) => Ok(DiskSpace::WarnFirst),
+        OnLowDiskSpace::Warn => Ok(DiskSpace::Enough),
+        OnLowDiskSpace::PurgeRotated => {
+            let mut index = 1;
+            while rotated_path(path, index).exists() {
+                index += 1;
+            }
+            while index > 1 && is_low() {
+                index -= 1;
+                let rotated = rotated_path(path, index);
+                fs::remove_file(&rotated).map_err(io_error(&rotated))?;
+            }
+            Ok(DiskSpace::Enough)
+        }
+    }
+}
+
+// The Warn record about the low disk space, a JSON object if `record` is one
+fn low_disk_space_warning(guard: DiskSpaceGuard, record: &str) -> String {
+    let message = format!("Free disk space is below {} bytes", guard.min_free_bytes);
+    if hash_chain::json_object(record.strip_suffix('\n').unwrap_or(record)).is_some() {
+        log_backend::format_json(
+            SystemTime::now(),
+            log::Level::Warn,
+            env!("CARGO_PKG_NAME"),
+            &message,
+        ) + "\n"
+    } else {
+        format!("[{}] {}\n", LogLevel::Warn, message)
+    }
+}
+
+// The directory the log file is in, the current one for a bare file name
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+// Free space of the file system containing `path`, None if it can't be determined
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = std::ffi::CString::new(parent_dir(path).as_os_str().as_bytes()).ok()?;
+
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `dir` is a valid NUL-terminated string and `stat` is only read after a successful call
+    if unsafe { libc::statvfs(dir.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+// Free space available to the current user, i.e. with the disk quotas applied
+#[cfg(windows)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory_name: *const u16,
+            free_bytes_available_to_caller: *mut u64,
+            total_number_of_bytes: *mut u64,
+            total_number_of_free_bytes: *mut u64,
+        ) -> i32;
+    }
+
+    let dir: Vec<u16> = parent_dir(path)
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+
+    let mut available = 0;
+    // SAFETY: `dir` is a valid NUL-terminated wide string, the totals are optional and may be null
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            dir.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    (ok != 0).then_some(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+// Opens the log file in append mode, so every write goes to the current end of the file
+// even if other threads or processes append to it too:
+// * Unix: O_APPEND.
+// * Windows: FILE_APPEND_DATA, shared for reading, writing and deleting, so other writers
+//   aren't locked out and the file can still be renamed/deleted by rotation tools.
+//
+// Missing parent directories are created. On Unix, new files and directories get the modes
+// and the owner configured in `options`.
+//
+// The file is opened for every record, so when an external tool renames, deletes or truncates
+// it (logrotate, copytruncate, manual rotation), the next record goes to the configured path.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn open_log_file(options: &LogOptions, path: &Path) -> Result<fs::File, LogError> {
+    let open_error = io_error(path);
+
+    if let Some(parent) = path.parent() {
+        let missing_dirs: Vec<&Path> = parent
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .collect();
+
+        if !missing_dirs.is_empty() {
+            let mut dir_builder = fs::DirBuilder::new();
+            dir_builder.recursive(true);
+
+            #[cfg(unix)]
+            if let Some(mode) = options.dir_mode {
+                use std::os::unix::fs::DirBuilderExt;
+                dir_builder.mode(mode);
+            }
+
+            dir_builder.create(parent).map_err(open_error)?;
+
+            #[cfg(unix)]
+            if let Some(owner) = options.owner {
+                for dir in &missing_dirs {
+                    std::os::unix::fs::chown(dir, owner.uid, owner.gid).map_err(open_error)?;
+                }
+            }
+        }
+    }
+
+    let created = !path.exists();
+    let mut open_options = fs::OpenOptions::new();
+    open_options.append(true).create(true);
+
+    #[cfg(unix)]
+    if let Some(mode) = options.file_mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        open_options.mode(mode);
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        const FILE_SHARE_READ: u32 = 0x1;
+        const FILE_SHARE_WRITE: u32 = 0x2;
+        const FILE_SHARE_DELETE: u32 = 0x4;
+        open_options.share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE);
+    }
+
+    let file = open_options.open(path).map_err(open_error)?;
+
+    #[cfg(unix)]
+    if let (true, Some(owner)) = (created, options.owner) {
+        std::os::unix::fs::fchown(&file, owner.uid, owner.gid).map_err(open_error)?;
+    }
+
+    Ok(file)
+}
+
+// Appends a record to the file at `path`, chained to the previous ones if enabled
+fn append_record(
+    options: &LogOptions,
+    path: &Path,
+    file: &mut fs::File,
+    line: &str,
+) -> Result<usize, LogError> {
+    if options.hash_chain {
+        hash_chain::append(path, file, line)
+    } else {
+        append_line(path, file, line)
+    }
+}
+
+// Appends the line, ending with a newline, with a single write call, so concurrent appends
+// never interleave within a line (writeln! may split it into several writes). On Unix, appends
+// up to PIPE_BUF (4096) bytes are atomic on any file system, local file systems keep larger
+// ones intact too.
+fn append_line(path: &Path, file: &mut fs::File, line: &str) -> Result<usize, LogError> {
+    file.write_all(line.as_bytes()).map_err(io_error(path))?;
+    Ok(line.len())
+}
+
+// Absolute Windows paths get the extended-length prefix, so paths longer than MAX_PATH work:
+// `C:\logs\log.txt` -> `\\?\C:\logs\log.txt`, `\\server\share\log.txt` -> `\\?\UNC\server\share\log.txt`.
+#[cfg(windows)]
+fn platform_path(path: &Path) -> Cow<'_, Path> {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
+
+    let extended_prefix = match path.components().next() {
+        Some(Component::Prefix(prefix)) if path.is_absolute() => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut extended = OsString::from(r"\\?\");
+                extended.push(prefix.as_os_str());
+                extended
+            }
+            Prefix::UNC(server, share) => {
+                let mut extended = OsString::from(r"\\?\UNC\");
+                extended.push(server);
+                extended.push(r"\");
+                extended.push(share);
+                extended
+            }
+            // Already verbatim (`\\?\...`) or a device path
+            _ => return Cow::Borrowed(path),
+        },
+        _ => return Cow::Borrowed(path),
+    };
+
+    // Rebuilt from the components, verbatim paths don't accept `/` separators
+    let mut extended = PathBuf::from(extended_prefix);
+    extended.push(r"\");
+    extended.extend(path.components().skip(2));
+    Cow::Owned(extended)
+}
+
+#[cfg(not(windows))]
+fn platform_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+// `log.txt` -> `log.txt.1`
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+// Deletes `<path>.N` with the highest N (the oldest) while the total size exceeds `max_total_bytes`
+fn enforce_retention(path: &Path, max_total_bytes: u64) -> std::io::Result<()> {
+    let len = |path: &Path| fs::metadata(path).map_or(0, |metadata| metadata.len());
+
+    let mut rotated: Vec<PathBuf> = (1..)
+        .map(|index| rotated_path(path, index))
+        .take_while(|rotated| rotated.exists())
+        .collect();
+    let mut total = len(path) + rotated.iter().map(|rotated| len(rotated)).sum::<u64>();
+
+    while total > max_total_bytes {
+        let Some(oldest) = rotated.pop() else {
+            break;
+        };
+        total -= len(&oldest);
+        fs::remove_file(oldest)?;
+    }
+
+    Ok(())
+}
+
+// Shifts `<path>.N` to `<path>.N+1` dropping the ones past `keep`, then moves `path` to `<path>.1`
+fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+
+    match fs::remove_file(rotated_path(path, keep)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    for index in (1..keep).rev() {
+        match fs::rename(rotated_path(path, index), rotated_path(path, index + 1)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        let _ = write!(out, "{:02x}", byte);
+    }
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+// Tamper-evident files: every record ends with ` #<hash>`, the SHA-256 of the hash of the previous
+// record and the record itself, the first record of a file is chained to GENESIS. Changing,
+// inserting, removing or reordering a record changes all the following hashes. A JSON object
+// record (Format::Json) gets a last `"hash":"<hash>"` field instead, so the file stays NDJSON.
+pub mod hash_chain {
+    use super::{append_line, decode_hex, io_error, push_hex, signature, LogError, LogOptions};
+    use sha2::{Digest, Sha256};
+    use std::fs;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    /// The hash the chain of every file starts from.
+    pub const GENESIS: [u8; 32] = [0; 32];
+
+    // " #" and 64 hex digits, the newline is already counted in the record
+    const SUFFIX_LEN: usize = 66;
+
+    // Replaces the closing brace of a JSON object record, followed by 64 hex digits and `"}`
+    const JSON_FIELD: &str = ",\"hash\":\"";
+    const JSON_SUFFIX_LEN: usize = JSON_FIELD.len() + 64 + 1;
+
+    // Reading the last hash and appending the next record must not interleave between threads
+    static APPEND_LOCK: Mutex<()> = Mutex::new(());
+
+    // Extra bytes `line` takes in the file with `options`
+    pub(super) fn suffix_len(options: &LogOptions, line: &str) -> u64 {
+        if !options.hash_chain {
+            0
+        } else if json_object(line.strip_suffix('\n').unwrap_or(line)).is_some() {
+            JSON_SUFFIX_LEN as u64
+        } else {
+            SUFFIX_LEN as u64
+        }
+    }
+
+    // The record without its closing brace if it's a JSON object
+    pub(super) fn json_object(record: &str) -> Option<&str> {
+        record
+            .strip_suffix('}')
+            .filter(|object| object.starts_with('{'))
+    }
+
+    fn next_hash(previous: &[u8; 32], record: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(previous);
+        hasher.update(record.as_bytes());
+        hasher.finalize().into()
+    }
+
+    // Splits `record #<hash>` or `{...,"hash":"<hash>"}` into the length of the record before the
+    // hash, the closing brace the hash field took the place of, if any, and the hash
+    fn split_hash(line: &[u8]) -> Option<(usize, &'static str, [u8; 32])> {
+        let (rest, marker, closing) = match line.strip_suffix(b"\"}") {
+            Some(rest) => (rest, JSON_FIELD, "}"),
+            None => (line, " #", ""),
+        };
+        let (record, hex) = rest.split_at_checked(rest.len().checked_sub(64)?)?;
+        let record = record.strip_suffix(marker.as_bytes())?;
+        Some((
+            record.len(),
+            closing,
+            decode_hex(std::str::from_utf8(hex).ok()?)?,
+        ))
+    }
+
+    // Hash of the last record of the file, GENESIS if the file is empty or missing
+    fn last_hash(path: &Path) -> Result<[u8; 32], LogError> {
+        let read_error = io_error(path);
+        let mut file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(GENESIS),
+            Err(e) => return Err(read_error(e)),
+        };
+
+        let len = file.metadata().map_err(read_error)?.len();
+        if len == 0 {
+            return Ok(GENESIS);
+        }
+
+        // Bytes, the tail may start in the middle of a character
+        let mut tail = Vec::new();
+        let tail_len = (JSON_SUFFIX_LEN.max(SUFFIX_LEN) as u64 + 2).min(len);
+        file.seek(SeekFrom::End(-(tail_len as i64)))
+            .map_err(read_error)?;
+        file.read_to_end(&mut tail).map_err(read_error)?;
+
+        tail.strip_suffix(b"\n")
+            .and_then(split_hash)
+            .map(|(_, _, hash)| hash)
+            .ok_or_else(|| {
+                LogError::Format(format!(
+                    "{} doesn't end with a chained record",
+                    path.display()
+                ))
+            })
+    }
+
+    // Appends `line`, ending with a newline, with the hash of the chain
+    pub(super) fn append(path: &Path, file: &mut fs::File, line: &str) -> Result<usize, LogError> {
+        let _lock = APPEND_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let record = line.strip_suffix('\n').unwrap_or(line);
+        let hash = next_hash(&last_hash(path)?, record);
+
+        let mut chained = String::with_capacity(record.len() + JSON_SUFFIX_LEN + 1);
+        match json_object(record) {
+            Some(object) => {
+                chained.push_str(object);
+                chained.push_str(JSON_FIELD);
+                push_hex(&mut chained, &hash);
+                chained.push_str("\"}");
+            }
+            None => {
+                chained.push_str(record);
+                chained.push_str(" #");
+                push_hex(&mut chained, &hash);
+            }
+        }
+        chained.push('\n');
+
+        append_line(path, file, &chained)
+    }
+
+    /// Checks the hash chain of a file written with [`LogOptions::hash_chain`], returns the number
+    /// of records.
+    ///
+    /// Fails at the first record that has been modified, inserted, removed or moved, or if the
+    /// file has been cut in the middle of a record. Whole records cut off the end of the file
+    /// can't be detected from the file alone, keep the number of records or the last hash
+    /// elsewhere to detect those too.
+    pub fn verify(path: &Path) -> Result<usize, LogError> {
+        let content = fs::read_to_string(path).map_err(io_error(path))?;
+        // The signature of a rotated file isn't a record
+        let content = signature::split(&content).map_or(content.as_str(), |(signed, _)| signed);
+        let broken = |line: usize, reason: &str| {
+            LogError::Format(format!("{}:{}: {}", path.display(), line, reason))
+        };
+
+        let mut previous = GENESIS;
+        let mut records = 0;
+        // Multi-line records only have the hash at the end of their last line
+        let mut record = String::new();
+
+        for (i, line) in content.split_inclusive('\n').enumerate() {
+            let line = line
+                .strip_suffix('\n')
+                .ok_or_else(|| broken(i + 1, "incomplete record"))?;
+
+            match split_hash(line.as_bytes()) {
+                Some((record_len, closing, hash)) => {
+                    record.push_str(&line[..record_len]);
+                    record.push_str(closing);
+                    if next_hash(&previous, &record) != hash {
+                        return Err(broken(i + 1, "hash chain broken"));
+                    }
+                    previous = hash;
+                    records += 1;
+                    record.clear();
+                }
+                None => {
+                    record.push_str(line);
+                    record.push('\n');
+                }
+            }
+        }
+
+        if record.is_empty() {
+            Ok(records)
+        } else {
+            Err(broken(content.lines().count(), "record without a hash"))
+        }
+    }
+}
+
+// Signed rotated files: when the file log type rotates with a signing key, the rotated file gets
+// a last line `#signature ed25519 <signature>` over everything before it, so the consumers
+// holding the verifying key can check where the file comes from.
+pub mod signature {
+    use super::{decode_hex, io_error, push_hex, LogError};
+    use ed25519_dalek::{Signature, Signer, Verifier};
+    use std::fs;
+    use std::path::Path;
+
+    pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+    const PREFIX: &str = "#signature ed25519 ";
+
+    // Splits a signed file into the signed content and the signature
+    pub(super) fn split(content: &str) -> Option<(&str, Signature)> {
+        let body = content.strip_suffix('\n')?;
+        let start = body.rfind('\n').map_or(0, |i| i + 1);
+        let hex = body[start..].strip_prefix(PREFIX)?;
+        Some((&content[..start], Signature::from_bytes(&decode_hex(hex)?)))
+    }
+
+    // Signs the whole file at `path` and appends the signature line
+    pub(super) fn sign(path: &Path, key: &SigningKey) -> Result<(), LogError> {
+        let content = fs::read(path).map_err(io_error(path))?;
+        let signature = key.sign(&content);
+
+        let mut line = String::from(PREFIX);
+        push_hex(&mut line, &signature.to_bytes());
+        line.push('\n');
+
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(io_error(path))?;
+        super::append_line(path, &mut file, &line)?;
+        Ok(())
+    }
+
+    /// Checks the signature line of a rotated file signed with [`super::LogOptions::signing_key`].
+    ///
+    /// Fails if the file isn't signed, has been changed after signing, or has been signed with
+    /// another key.
+    pub fn verify(path: &Path, key: &VerifyingKey) -> Result<(), LogError> {
+        let content = fs::read_to_string(path).map_err(io_error(path))?;
+        let (signed, signature) = split(&content).ok_or_else(|| {
+            LogError::Format(format!("{} doesn't end with a signature", path.display()))
+        })?;
+
+        key.verify(signed.as_bytes(), &signature)
+            .map_err(|_| LogError::Format(format!("{}: signature doesn't match", path.display())))
+    }
+}
+
+// HTTP access logs in the Apache/NCSA combined format, e.g.
+// 127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] "GET /a.gif HTTP/1.0" 200 2326 "http://x/" "Mozilla/4.08"
+// written without the `[LEVEL]` header, so the usual access log parsers read the files as they are.
+pub mod access_log {
+    use super::{
+        is_bidi_control, with_record_buffer, write_line, LogError, LogLevel, LogOptions, LogType,
+        Written,
+    };
+    use std::fmt::Write as _;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// A served HTTP request, the fields that are `None` are written as `-`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AccessRecord<'a> {
+        pub client: &'a str,
+        pub user: Option<&'a str>,
+        pub time: SystemTime,
+        pub method: &'a str,
+        pub path: &'a str,
+        pub protocol: &'a str,
+        pub status: u16,
+        pub bytes: Option<u64>,
+        pub referer: Option<&'a str>,
+        pub user_agent: Option<&'a str>,
+        /// Appended in microseconds after the combined fields, like Apache's `%D`.
+        pub latency: Option<Duration>,
+    }
+
+    /// Formats `record` as a combined log format line, without the newline.
+    pub fn format_combined(record: &AccessRecord, out: &mut String) {
+        push_field(out, Some(record.client));
+        out.push_str(" - ");
+        push_field(out, record.user);
+
+        out.push_str(" [");
+        push_time(out, record.time);
+        out.push_str("] \"");
+        push_escaped(out, record.method);
+        out.push(' ');
+        push_escaped(out, record.path);
+        out.push(' ');
+        push_escaped(out, record.protocol);
+
+        let _ = write!(out, "\" {} ", record.status);
+        match record.bytes {
+            Some(bytes) => {
+                let _ = write!(out, "{}", bytes);
+            }
+            None => out.push('-'),
+        }
+
+        for quoted in [record.referer, record.user_agent] {
+            out.push_str(" \"");
+            match quoted {
+                Some(value) => push_escaped(out, value),
+                None => out.push('-'),
+            }
+            out.push('"');
+        }
+
+        if let Some(latency) = record.latency {
+            let _ = write!(out, " {}", latency.as_micros());
+        }
+    }
+
+    /// Writes `record` to `log_type` in the combined log format, see [`format_combined`].
+    ///
+    /// `options` apply to the file (path, cap, rotation, ...), not to the formatting: the fields
+    /// are escaped the way Apache escapes them, and the line has no level header.
+    /// Server errors (5xx) count as Error records and the rest as Info, e.g. for
+    /// [`super::SyncPolicy::PerError`].
+    pub fn write_access_log(
+        options: &LogOptions,
+        log_type: LogType,
+        record: &AccessRecord,
+    ) -> Result<Written, LogError> {
+        let log_level = if record.status >= 500 {
+            LogLevel::Error
+        } else {
+            LogLevel::Info
+        };
+
+        with_record_buffer(|line| {
+            format_combined(record, line);
+            line.push('\n');
+            write_line(options, log_type, log_level, line)
+        })
+    }
+
+    fn push_field(out: &mut String, value: Option<&str>) {
+        match value {
+            Some(value) if !value.is_empty() => push_escaped(out, value),
+            _ => out.push('-'),
+        }
+    }
+
+    // Escapes quotes, backslashes and ASCII control characters like Apache does
+    // (`\"`, `\\`, `\xhh`), so a request can't break the line format.
+    fn push_escaped(out: &mut String, value: &str) {
+        for c in value.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                // Byte by byte, the way Apache escapes the non-printable characters
+                c if c.is_control() || is_bidi_control(c) => {
+                    for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                        let _ = write!(out, "\\x{:02x}", byte);
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    // `10/Oct/2000:13:55:36 +0000`, always in UTC
+    fn push_time(out: &mut String, time: SystemTime) {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let secs_of_day = secs % 86_400;
+        let _ = write!(
+            out,
+            "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60
+        );
+    }
+
+    // Days since 1970-01-01 to (year, month, day), Howard Hinnant's civil_from_days
+    fn civil_from_days(days: i64) -> (i64, u32, u32) {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+}
+
+mod external_log {
+    pub fn write_to_log<T>(value: T)
+    where
+        T: AsRef<str>,
+    {
+        log::info!("{}", value.as_ref());
+    }
+}
+
+// The time source of the timestamps, swapped for a MockClock in tests
+pub mod clock {
+    #[cfg(any(test, feature = "test-support"))]
+    use std::sync::{Arc, Mutex};
+    #[cfg(any(test, feature = "test-support"))]
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    /// Where the timestamps of the records come from.
+    pub trait Clock: Send + Sync {
+        fn now(&self) -> SystemTime;
+    }
+
+    /// The system wall clock, the default.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> SystemTime {
+            SystemTime::now()
+        }
+    }
+
+    /// A clock that only moves when told to, so the timestamps in tests are known in advance.
+    ///
+    /// Clones share the time, keep one to move the clock given to a logger. Only built with the
+    /// `test-support` feature.
+    #[cfg(any(test, feature = "test-support"))]
+    #[derive(Debug, Clone)]
+    pub struct MockClock {
+        now: Arc<Mutex<SystemTime>>,
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    impl MockClock {
+        /// Creates a clock stopped at `now`.
+        pub fn new(now: SystemTime) -> Self {
+            Self {
+                now: Arc::new(Mutex::new(now)),
+            }
+        }
+
+        /// Moves the clock to `now`, possibly backwards.
+        pub fn set(&self, now: SystemTime) {
+            *self.lock() = now;
+        }
+
+        /// Moves the clock forward by `duration`.
+        pub fn advance(&self, duration: Duration) {
+            *self.lock() += duration;
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, SystemTime> {
+            self.now
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            *self.lock()
+        }
+    }
+}
+
+// The opposite direction of external_log: records emitted by any dependency
+// through the log crate facade (log::warn!, ...) are forwarded to write_to_log.
+pub mod log_backend {
+    use super::clock::{Clock, SystemClock};
+    use super::{
+        is_bidi_control, report_internal_error, with_buffer, with_record_buffer, write_line,
+        LogError, LogLevel, LogOptions, LogType, Written,
+    };
+    use std::cell::RefCell;
+    use std::ffi::OsStr;
+    use std::fmt::{self, Debug, Display, Write as _};
+    use std::io::{BufRead, IsTerminal};
+    use std::time::SystemTime;
+
+    /// How the backend formats the records.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Format {
+        /// `[LEVEL] target: message`, see [`format_plain`].
+        Plain,
+        /// `[timestamp LEVEL target] message`, the default output of env_logger, see [`format_env_logger`].
+        EnvLogger,
+        /// One JSON object per line (NDJSON), `{"time":"...","level":"INFO","target":"...","message":"..."}`,
+        /// see [`format_json`] and [`read_json_lines`]. Never styled, [`LogOptions::multi_line`] and
+        /// [`LogOptions::escape_control_chars`] don't apply, JSON escapes the newlines,
+        /// the other control characters and the bidi overrides itself, as `\uXXXX`.
+        Json,
+    }
+
+    /// An ANSI SGR style, e.g. `Style("1;31")` for bold red.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Style(pub &'static str);
+
+    impl Style {
+        /// No escape codes at all.
+        pub const NONE: Style = Style("");
+
+        fn paint<'a>(&self, text: &'a dyn Display) -> Painted<'a> {
+            Painted { style: *self, text }
+        }
+    }
+
+    // A text with a style, written without building an intermediate String
+    struct Painted<'a> {
+        style: Style,
+        text: &'a dyn Display,
+    }
+
+    impl Display for Painted<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if self.style.0.is_empty() {
+                write!(f, "{}", self.text)
+            } else {
+                write!(f, "\x1b[{}m{}\x1b[0m", self.style.0, self.text)
+            }
+        }
+    }
+
+    /// Styles of the level and the timestamp/target segments of the console output.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Theme {
+        pub error: Style,
+        pub warn: Style,
+        pub info: Style,
+        pub debug: Style,
+        pub timestamp: Style,
+        pub target: Style,
+    }
+
+    impl Theme {
+        /// No styles, the output is the same as without a theme.
+        pub const PLAIN: Theme = Theme {
+            error: Style::NONE,
+            warn: Style::NONE,
+            info: Style::NONE,
+            debug: Style::NONE,
+            timestamp: Style::NONE,
+            target: Style::NONE,
+        };
+
+        /// Bright colors for dark terminal backgrounds.
+        pub fn dark() -> Self {
+            Theme {
+                error: Style("1;91"),
+                warn: Style("93"),
+                info: Style("92"),
+                debug: Style("94"),
+                timestamp: Style("90"),
+                target: Style("97"),
+            }
+        }
+
+        /// Darker colors for light terminal backgrounds.
+        pub fn light() -> Self {
+            Theme {
+                error: Style("1;31"),
+                warn: Style("33"),
+                info: Style("32"),
+                debug: Style("34"),
+                timestamp: Style("37"),
+                target: Style("30"),
+            }
+        }
+
+        /// No colors, severe levels are emphasized with bold/underline instead.
+        pub fn monochrome_bold() -> Self {
+            Theme {
+                error: Style("1;4"),
+                warn: Style("1"),
+                info: Style::NONE,
+                debug: Style("2"),
+                timestamp: Style("2"),
+                target: Style::NONE,
+            }
+        }
+
+        fn level(&self, level: LogLevel) -> Style {
+            match level {
+                LogLevel::Error => self.error,
+                LogLevel::Warn => self.warn,
+                LogLevel::Info => self.info,
+                LogLevel::Debug => self.debug,
+            }
+        }
+    }
+
+    /// Whether the console output is styled with the theme.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ColorChoice {
+        /// Style only if stdout is a terminal and neither `NO_COLOR` nor `CLICOLOR=0` is set.
+        Auto,
+        Always,
+        Never,
+    }
+
+    impl ColorChoice {
+        /// Resolves the choice against the current stdout and environment.
+        pub fn use_colors(self) -> bool {
+            self.use_colors_with(
+                std::io::stdout().is_terminal(),
+                std::env::var_os("NO_COLOR").as_deref(),
+                std::env::var_os("CLICOLOR").as_deref(),
+            )
+        }
+
+        // See https://no-color.org, an empty NO_COLOR doesn't disable colors
+        fn use_colors_with(
+            self,
+            is_terminal: bool,
+            no_color: Option<&OsStr>,
+            clicolor: Option<&OsStr>,
+        ) -> bool {
+            match self {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => {
+                    is_terminal
+                        && no_color.is_none_or(OsStr::is_empty)
+                        && clicolor.is_none_or(|value| value != "0")
+                }
+            }
+        }
+    }
+
+    /// Formats a record the same way [`Format::Plain`] does with the default [`LogOptions`],
+    /// i.e. `[LEVEL] target: message`, the control characters escaped and the continuation lines
+    /// indented.
+    pub fn format_plain(
+        level: LogLevel,
+        target: &str,
+        message: &dyn Display,
+        theme: &Theme,
+    ) -> String {
+        let header = Header::Plain {
+            level,
+            target,
+            theme,
+        };
+        format_with_default_options(&header, message)
+    }
+
+    /// Formats a record the way env_logger does by default, e.g.
+    /// `[2023-10-01T12:00:00Z INFO  my_crate] message`. The message goes through the default
+    /// [`LogOptions`] the same way as with [`format_plain`].
+    pub fn format_env_logger(
+        time: SystemTime,
+        level: log::Level,
+        target: &str,
+        message: &dyn Display,
+        theme: &Theme,
+    ) -> String {
+        let header = Header::EnvLogger {
+            time,
+            level,
+            target,
+            theme,
+        };
+        format_with_default_options(&header, message)
+    }
+
+    fn format_with_default_options(header: &dyn Display, message: &dyn Display) -> String {
+        let mut formatted = String::new();
+        LogOptions::default().join_into(&mut formatted, header, &message.to_string());
+        formatted
+    }
+
+    /// Formats a record as a JSON object, the same way [`Format::Json`] does.
+    pub fn format_json(time: SystemTime, level: log::Level, target: &str, message: &str) -> String {
+        let mut formatted = String::new();
+        push_json(&mut formatted, time, level, target, message);
+        formatted
+    }
+
+    fn push_json(
+        out: &mut String,
+        time: SystemTime,
+        level: log::Level,
+        target: &str,
+        message: &str,
+    ) {
+        let _ = write!(
+            out,
+            "{{\"time\":\"{}\",\"level\":\"{}\",\"target\":",
+            humantime::format_rfc3339_seconds(time),
+            level
+        );
+        push_json_string(out, target);
+        out.push_str(",\"message\":");
+        push_json_string(out, message);
+        out.push('}');
+    }
+
+    fn push_json_string(out: &mut String, value: &str) {
+        out.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                // C0 and C1 controls and bidi overrides, so a viewer can't be tricked either
+                c if c.is_control() || is_bidi_control(c) => {
+                    let _ = write!(out, "\\u{:04x}", c as u32);
+                }
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+
+    /// A record of the [`Format::Json`] output, read back by [`read_json_lines`].
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+    pub struct JsonRecord {
+        /// RFC 3339, e.g. `2023-10-01T12:00:00Z`.
+        pub time: String,
+        pub level: String,
+        pub target: String,
+        pub message: String,
+    }
+
+    /// Reads the records of a [`Format::Json`] file, one per line. Empty lines are skipped,
+    /// malformed ones are returned as errors without stopping the iteration.
+    pub fn read_json_lines<R: BufRead>(
+        reader: R,
+    ) -> impl Iterator<Item = Result<JsonRecord, LogError>> {
+        reader
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| {
+                let line = line.map_err(|source| LogError::Io { path: None, source })?;
+                serde_json::from_str(&line).map_err(|e| LogError::Format(e.to_string()))
+            })
+    }
+
+    // The header of a record in one of the formats, written straight into the record buffer
+    enum Header<'a> {
+        Plain {
+            level: LogLevel,
+            target: &'a str,
+            theme: &'a Theme,
+        },
+        EnvLogger {
+            time: SystemTime,
+            level: log::Level,
+            target: &'a str,
+            theme: &'a Theme,
+        },
+    }
+
+    impl Display for Header<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match *self {
+                Header::Plain {
+                    level,
+                    target,
+                    theme,
+                } => write!(
+                    f,
+                    "{} {}:",
+                    theme.level(level).paint(&format_args!("[{}]", level)),
+                    theme.target.paint(&target),
+                ),
+                Header::EnvLogger {
+                    time,
+                    level,
+                    target,
+                    theme,
+                } => write!(
+                    f,
+                    "[{} {} {}]",
+                    theme
+                        .timestamp
+                        .paint(&humantime::format_rfc3339_seconds(time)),
+                    theme
+                        .level(LogLevel::from(level))
+                        .paint(&format_args!("{:<5}", level)),
+                    theme.target.paint(&target),
+                ),
+            }
+        }
+    }
+
+    thread_local! {
+        static MESSAGE_BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
+    }
+
+    // Calls `f` with the formatted arguments, in a reused buffer unless it's a plain string
+    fn with_message<R>(args: &fmt::Arguments, f: impl FnOnce(&str) -> R) -> R {
+        match args.as_str() {
+            Some(message) => f(message),
+            None => with_buffer(&MESSAGE_BUFFER, |message| {
+                let _ = message.write_fmt(*args);
+                f(message)
+            }),
+        }
+    }
+
+    /// A `log::Log` implementation forwarding records to [`super::write_to_log`].
+    pub struct LogBackend {
+        max_level: log::LevelFilter,
+        default_log_type: LogType,
+        targets: Vec<(String, LogType)>,
+        format: Format,
+        theme: Theme,
+        use_colors: bool,
+        options: LogOptions,
+        clock: Box<dyn Clock>,
+    }
+
+    impl LogBackend {
+        /// Creates a backend sending records up to `max_level` to `default_log_type`.
+        pub fn new(max_level: log::LevelFilter, default_log_type: LogType) -> Self {
+            Self {
+                max_level,
+                default_log_type,
+                targets: Vec::new(),
+                format: Format::Plain,
+                theme: Theme::PLAIN,
+                use_colors: ColorChoice::Auto.use_colors(),
+                options: LogOptions::default(),
+                clock: Box::new(SystemClock),
+            }
+        }
+
+        /// Sets the options applied to every record, see [`super::write_to_log_with`].
+        pub fn with_options(mut self, options: LogOptions) -> Self {
+            self.options = options;
+            self
+        }
+
+        /// Styles the console output with `theme`, [`Theme::PLAIN`] by default.
+        ///
+        /// The other log types are never styled, so log files don't end up with escape codes.
+        /// The theme is ignored if the colors are disabled, see [`LogBackend::with_color`].
+        pub fn with_theme(mut self, theme: Theme) -> Self {
+            self.theme = theme;
+            self
+        }
+
+        /// Overrides whether the console output is styled, [`ColorChoice::Auto`] by default.
+        pub fn with_color(mut self, choice: ColorChoice) -> Self {
+            self.use_colors = choice.use_colors();
+            self
+        }
+
+        /// Overrides where the timestamps of [`Format::EnvLogger`] and [`Format::Json`] come
+        /// from, [`SystemClock`] by default.
+        pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+            self.clock = Box::new(clock);
+            self
+        }
+
+        /// Selects how the records are formatted, [`Format::Plain`] by default.
+        pub fn with_format(mut self, format: Format) -> Self {
+            self.format = format;
+            self
+        }
+
+        /// Sends records whose target starts with `target_prefix` to `log_type`.
+        ///
+        /// The longest matching prefix wins, i.e. `my_crate::net` is preferred over `my_crate`.
+        pub fn with_target(mut self, target_prefix: &str, log_type: LogType) -> Self {
+            self.targets.push((target_prefix.to_string(), log_type));
+            self
+        }
+
+        /// Writes the resolved configuration as Info records to every log type the backend sends
+        /// records to, e.g. `startup version=0.1.0 max_level=DEBUG ...`, so the logs show how
+        /// they were produced. Call it before [`install`], which takes the backend.
+        pub fn log_startup_info(&self) -> Result<(), LogError> {
+            let mut log_types = vec![self.default_log_type];
+            for (_, log_type) in &self.targets {
+                if !log_types.contains(log_type) {
+                    log_types.push(*log_type);
+                }
+            }
+
+            let mut messages = vec![format!(
+                "startup version={} max_level={} format={:?} default_log_type={:?} colors={}",
+                env!("CARGO_PKG_VERSION"),
+                self.max_level,
+                self.format,
+                self.default_log_type,
+                self.use_colors,
+            )];
+            for (target_prefix, log_type) in &self.targets {
+                messages.push(format!(
+                    "startup target={} log_type={:?}",
+                    target_prefix, log_type
+                ));
+            }
+            if log_types.contains(&LogType::FileSystem) {
+                // Values containing spaces are quoted, so every field stays a single key=value
+                let quoted = |value: &dyn Debug| format!("{:?}", format!("{:?}", value));
+                let options = &self.options;
+                messages.push(format!(
+                    "startup file_path={:?} max_file_size={} max_total_size={:?} \
+                     disk_space_guard={} sync_policy={:?}",
+                    options.file_path,
+                    quoted(&options.max_file_size),
+                    options.max_total_size,
+                    quoted(&options.disk_space_guard),
+                    options.sync_policy,
+                ));
+            }
+
+            for log_type in log_types {
+                for message in &messages {
+                    self.write_record(log_type, log::Level::Info, env!("CARGO_PKG_NAME"), message)?;
+                }
+            }
+
+            Ok(())
+        }
+
+        // Formats a record the way the backend is configured to and writes it to `log_type`
+        fn write_record(
+            &self,
+            log_type: LogType,
+            level: log::Level,
+            target: &str,
+            message: &str,
+        ) -> Result<Written, LogError> {
+            let theme = match log_type {
+                LogType::Console if self.use_colors => &self.theme,
+                _ => &Theme::PLAIN,
+            };
+
+            with_record_buffer(|line| {
+                match self.format {
+                    Format::Plain => {
+                        let header = Header::Plain {
+                            level: LogLevel::from(level),
+                            target,
+                            theme,
+                        };
+                        self.options.join_into(line, &header, message)
+                    }
+                    Format::EnvLogger => {
+                        let header = Header::EnvLogger {
+                            time: self.clock.now(),
+                            level,
+                            target,
+                            theme,
+                        };
+                        self.options.join_into(line, &header, message)
+                    }
+                    Format::Json => push_json(
+                        line,
+                        self.clock.now(),
+                        level,
+                        target,
+                        &self.options.transform(message),
+                    ),
+                }
+                line.push('\n');
+                write_line(&self.options, log_type, LogLevel::from(level), line)
+            })
+        }
+
+        /// Returns the log type the records of `target` are sent to.
+        pub fn log_type_for(&self, target: &str) -> LogType {
+            self.targets
+                .iter()
+                .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map_or(self.default_log_type, |(_, log_type)| *log_type)
+        }
+    }
+
+    impl log::Log for LogBackend {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= self.max_level
+        }
+
+        fn log(&self, record: &log::Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+
+            let log_type = self.log_type_for(record.target());
+            let result = with_message(record.args(), |message| {
+                self.write_record(log_type, record.level(), record.target(), message)
+            });
+
+            // There is no caller to return the error to
+            if let Err(e) = result {
+                report_internal_error(&e);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Installs `backend` as the global logger of the log crate.
+    ///
+    /// Returns an error if another logger (e.g. env_logger) has already been installed.
+    pub fn install(backend: LogBackend) -> Result<(), log::SetLoggerError> {
+        let max_level = backend.max_level;
+        log::set_boxed_logger(Box::new(backend))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_color_choice_auto() {
+            let auto = ColorChoice::Auto;
+
+            assert!(auto.use_colors_with(true, None, None));
+            assert!(auto.use_colors_with(true, Some(OsStr::new("")), Some(OsStr::new("1"))));
+            assert!(!auto.use_colors_with(false, None, None));
+            assert!(!auto.use_colors_with(true, Some(OsStr::new("1")), None));
+            assert!(!auto.use_colors_with(true, None, Some(OsStr::new("0"))));
+            assert!(ColorChoice::Always.use_colors_with(false, Some(OsStr::new("1")), None));
+            assert!(!ColorChoice::Never.use_colors_with(true, None, None));
+        }
+    }
+}
+
+// Keeps the latest records in memory, for tests and for serving the recent logs
+pub mod memory_sink {
+    use super::clock::{Clock, SystemClock};
+    use super::LogLevel;
+    use regex::Regex;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+    use std::time::SystemTime;
+
+    /// A record kept by [`MemorySink`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct MemoryRecord {
+        pub time: SystemTime,
+        pub level: LogLevel,
+        pub target: String,
+        pub message: String,
+    }
+
+    /// A `log::Log` implementation keeping the latest `capacity` records, the oldest ones are
+    /// dropped first.
+    ///
+    /// Clones share the records, so one can be installed as the logger and another one queried.
+    #[derive(Clone)]
+    pub struct MemorySink {
+        max_level: log::LevelFilter,
+        capacity: usize,
+        records: Arc<Mutex<VecDeque<MemoryRecord>>>,
+        clock: Arc<dyn Clock>,
+    }
+
+    impl MemorySink {
+        /// Creates a sink keeping up to `capacity` records up to `max_level`.
+        pub fn new(max_level: log::LevelFilter, capacity: usize) -> Self {
+            Self {
+                max_level,
+                capacity,
+                records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity.min(1024)))),
+                clock: Arc::new(SystemClock),
+            }
+        }
+
+        /// Overrides where the timestamps of the records come from, see [`super::clock`].
+        pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+            self.clock = Arc::new(clock);
+            self
+        }
+
+        /// Adds a record, dropping the oldest one if the sink is full.
+        pub fn push(&self, record: MemoryRecord) {
+            if self.capacity == 0 {
+                return;
+            }
+            let mut records = self.lock();
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+
+        /// All the records, oldest first.
+        pub fn records(&self) -> Vec<MemoryRecord> {
+            self.filter(|_| true)
+        }
+
+        /// The records of `level`, oldest first.
+        pub fn by_level(&self, level: LogLevel) -> Vec<MemoryRecord> {
+            self.filter(|record| record.level == level)
+        }
+
+        /// The records whose message contains `pattern`, oldest first.
+        pub fn containing(&self, pattern: &str) -> Vec<MemoryRecord> {
+            self.filter(|record| record.message.contains(pattern))
+        }
+
+        /// The records from `start` (inclusive) to `end` (exclusive), oldest first.
+        pub fn between(&self, start: SystemTime, end: SystemTime) -> Vec<MemoryRecord> {
+            self.filter(|record| start <= record.time && record.time < end)
+        }
+
+        pub fn len(&self) -> usize {
+            self.lock().len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.lock().is_empty()
+        }
+
+        pub fn clear(&self) {
+            self.lock().clear();
+        }
+
+        fn filter(&self, predicate: impl Fn(&MemoryRecord) -> bool) -> Vec<MemoryRecord> {
+            self.lock()
+                .iter()
+                .filter(|record| predicate(record))
+                .cloned()
+                .collect()
+        }
+
+        fn lock(&self) -> MutexGuard<'_, VecDeque<MemoryRecord>> {
+            self.records
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    impl log::Log for MemorySink {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= self.max_level
+        }
+
+        fn log(&self, record: &log::Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+
+            self.push(MemoryRecord {
+                time: self.clock.now(),
+                level: LogLevel::from(record.level()),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    thread_local! {
+        // The sink of the innermost capture running on the thread
+        static CAPTURE: RefCell<Option<MemorySink>> = const { RefCell::new(None) };
+    }
+
+    // The global logger of the captures, forwarding each record to the capture of its thread
+    struct CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            CAPTURE.with(|capture| capture.borrow().is_some())
+        }
+
+        fn log(&self, record: &log::Record) {
+            let sink = CAPTURE.with(|capture| capture.borrow().clone());
+            if let Some(sink) = sink {
+                log::Log::log(&sink, record);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    // Restores the enclosing capture, also when the captured code panics
+    struct CaptureGuard {
+        previous: Option<MemorySink>,
+    }
+
+    impl Drop for CaptureGuard {
+        fn drop(&mut self) {
+            let previous = self.previous.take();
+            CAPTURE.with(|capture| *capture.borrow_mut() = previous);
+        }
+    }
+
+    /// Runs `f` and returns its result with every record it logged through the log crate, at any
+    /// level.
+    ///
+    /// Only the records of the current thread are captured, so tests running in parallel don't
+    /// see each other's records. The first capture installs the global logger of the log crate.
+    ///
+    /// # Panics
+    ///
+    /// If another logger has already been installed, e.g. with [`super::log_backend::install`].
+    pub fn capture<R>(f: impl FnOnce() -> R) -> (R, Vec<MemoryRecord>) {
+        static INSTALLED: OnceLock<bool> = OnceLock::new();
+        let installed = *INSTALLED.get_or_init(|| {
+            let installed = log::set_logger(&CaptureLogger).is_ok();
+            if installed {
+                log::set_max_level(log::LevelFilter::Trace);
+            }
+            installed
+        });
+        assert!(
+            installed,
+            "Another logger is installed, records can't be captured"
+        );
+
+        let sink = MemorySink::new(log::LevelFilter::Trace, usize::MAX);
+        let _guard = CaptureGuard {
+            previous: CAPTURE.with(|capture| capture.replace(Some(sink.clone()))),
+        };
+        let result = f();
+        (result, sink.records())
+    }
+
+    // Used by assert_logged! and assert_not_logged!
+    #[doc(hidden)]
+    #[track_caller]
+    pub fn assert_captured(records: &[MemoryRecord], level: LogLevel, pattern: &str, logged: bool) {
+        let regex = Regex::new(pattern).expect("Invalid pattern");
+        let found = records
+            .iter()
+            .any(|record| record.level == level && regex.is_match(&record.message));
+        if found != logged {
+            let captured: Vec<_> = records
+                .iter()
+                .map(|record| format!("[{}] {}", record.level, record.message))
+                .collect();
+            panic!(
+                "expected {} {} record matching `{}`, captured: {:#?}",
+                if logged { "a" } else { "no" },
+                level,
+                pattern,
+                captured
+            );
+        }
+    }
+}
+
+// Records with fixed fields, so tests and snapshots of the formatting don't change from one run
+// to the next. Only built with the test-support feature, release builds don't carry it.
+#[cfg(any(test, feature = "test-support"))]
+pub mod fixture {
+    use super::clock::MockClock;
+    use super::memory_sink::MemoryRecord;
+    use super::LogLevel;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// The time of the fixtures, 2023-10-01T12:00:00Z.
+    pub fn time() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_696_161_600)
+    }
+
+    /// A clock stopped at [`time`], to give to the logger under test.
+    pub fn clock() -> MockClock {
+        MockClock::new(time())
+    }
+
+    /// A record with stable fields, an Info record of the `my_crate` target by default, e.g.
+    ///
+    /// ```
+    /// use nxlog_task::task_1::fixture::{self, RecordFixture};
+    /// use nxlog_task::task_1::memory_sink::MemorySink;
+    /// use nxlog_task::task_1::LogLevel;
+    ///
+    /// let sink = MemorySink::new(log::LevelFilter::Debug, 16).with_clock(fixture::clock());
+    /// let record = RecordFixture::new("connected")
+    ///     .with_level(log::Level::Warn)
+    ///     .with_target("my_crate::db");
+    /// record.log(&sink);
+    ///
+    /// assert_eq!(sink.records(), vec![record.to_memory_record()]);
+    /// assert_eq!(sink.records()[0].level, LogLevel::Warn);
+    /// ```
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct RecordFixture {
+        level: log::Level,
+        target: String,
+        message: String,
+    }
+
+    impl RecordFixture {
+        /// Creates an Info record of the `my_crate` target with `message`.
+        pub fn new(message: impl Into<String>) -> Self {
+            Self {
+                level: log::Level::Info,
+                target: "my_crate".to_string(),
+                message: message.into(),
+            }
+        }
+
+        pub fn with_level(mut self, level: log::Level) -> Self {
+            self.level = level;
+            self
+        }
+
+        pub fn with_target(mut self, target: &str) -> Self {
+            self.target = target.to_string();
+            self
+        }
+
+        pub fn message(&self) -> &str {
+            &self.message
+        }
+
+        /// Sends the record to `logger`, with the line of this file as its location.
+        pub fn log(&self, logger: &dyn log::Log) {
+            logger.log(
+                &log::Record::builder()
+                    .level(self.level)
+                    .target(&self.target)
+                    .module_path_static(Some(module_path!()))
+                    .file_static(Some(file!()))
+                    .line(Some(line!()))
+                    .args(format_args!("{}", self.message))
+                    .build(),
+            );
+        }
+
+        /// The record as a [`MemoryRecord`] logged at [`time`].
+        pub fn to_memory_record(&self) -> MemoryRecord {
+            MemoryRecord {
+                time: time(),
+                level: LogLevel::from(self.level),
+                target: self.target.clone(),
+                message: self.message.clone(),
+            }
+        }
+    }
+
+    /// The messages formatters have trouble with, named: empty, unicode with combining
+    /// characters, LF and CRLF newlines, control and bidi characters, quotes and backslashes,
+    /// and a 200-byte field.
+    pub fn edge_cases() -> Vec<(&'static str, RecordFixture)> {
+        [
+            ("empty", String::new()),
+            (
+                "plain",
+                "Connection accepted from 192.168.0.12:51234".to_string(),
+            ),
+            (
+                "unicode",
+                "Grüße, 日本語, emoji 🦀, combining e\u{301}".to_string(),
+            ),
+            (
+                "newlines",
+                "Request failed:\n  at handler\r\n  at router\n".to_string(),
+            ),
+            (
+                "control",
+                "\x1b[31mred\x1b[0m bell\x07 tab\t bidi\u{202e}txt".to_string(),
+            ),
+            ("quotes", r#"say "hi" \ back\slash"#.to_string()),
+            ("huge", format!("key={}", "x".repeat(200))),
+        ]
+        .into_iter()
+        .map(|(name, message)| (name, RecordFixture::new(message)))
+        .collect()
+    }
+}
+
+/// Runs `$body`, a closure, with its records captured and asserts that one of them has
+/// `$level` and a message matching the `$pattern` regex. Evaluates to the result of `$body`.
+///
+/// See [`crate::task_1::memory_sink::capture`] for how the records are captured.
+///
+/// ```
+/// use nxlog_task::assert_logged;
+/// use nxlog_task::task_1::LogLevel;
+///
+/// let answer = assert_logged!(LogLevel::Warn, r"retry \d+", || {
+///     log::warn!("retry 3");
+///     42
+/// });
+/// assert_eq!(answer, 42);
+/// ```
+#[macro_export]
+macro_rules! assert_logged {
+    ($level:expr, $pattern:expr, $body:expr) => {{
+        let (result, records) = $crate::task_1::memory_sink::capture($body);
+        $crate::task_1::memory_sink::assert_captured(&records, $level, $pattern, true);
+        result
+    }};
+}
+
+/// Same as [`assert_logged!`], but asserts that no record has `$level` and a message matching
+/// `$pattern`.
+#[macro_export]
+macro_rules! assert_not_logged {
+    ($level:expr, $pattern:expr, $body:expr) => {{
+        let (result, records) = $crate::task_1::memory_sink::capture($body);
+        $crate::task_1::memory_sink::assert_captured(&records, $level, $pattern, false);
+        result
+    }};
+}
+
+/// Guard returned by [`start_heartbeat`], stops the heartbeat when dropped.
+#[must_use = "the heartbeat stops as soon as the guard is dropped"]
+pub struct Heartbeat {
+    // Dropping the sender wakes the thread up and makes it exit
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Writes an Info record `alive uptime_secs=<n> dropped_records=<n>` to `log_type` every
+/// `interval` from a background thread, until the returned guard is dropped. A service that hangs
+/// silently then shows up as a gap in the heartbeats.
+///
+/// # Arguments
+///
+/// * `options` - the options of the heartbeat records, see [`write_to_log_with`].
+///
+/// * `log_type` - a log target to accept the heartbeat records.
+///
+/// * `interval` - the time between two heartbeats, the first one is written after one interval.
+pub fn start_heartbeat(options: LogOptions, log_type: LogType, interval: Duration) -> Heartbeat {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let started = Instant::now();
+
+    let thread = thread::spawn(move || {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let message = format!(
+                "alive uptime_secs={} dropped_records={}",
+                started.elapsed().as_secs(),
+                dropped_records()
+            );
+            if let Err(e) = write_to_log_with(&options, log_type, LogLevel::Info, message) {
+                report_internal_error(&e);
+            }
+        }
+    });
+
+    Heartbeat {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+/// Installs a [`log_backend::LogBackend`] sending every record of the log crate,
+/// up to Debug, to the console.
+pub fn install_as_log_backend() -> Result<(), log::SetLoggerError> {
+    log_backend::install(log_backend::LogBackend::new(
+        log::LevelFilter::Debug,
+        LogType::Console,
+    ))
+}
+
+/// Logs the errors of a `Result` through the log crate facade (e.g. to an installed
+/// [`log_backend::LogBackend`]), or to a given logger with the `_to` methods, and passes the
+/// `Result` on unchanged:
+/// `let config = load_config().log_err_msg("loading the config")?;`
+pub trait ResultExt {
+    /// Logs the error as an Error record, followed by its sources: `error: cause: root cause`.
+    fn log_err(self) -> Self;
+
+    /// Same as [`ResultExt::log_err`], prefixed with the `context`: `context: error: cause`.
+    fn log_err_msg(self, context: &str) -> Self;
+
+    /// Same as [`ResultExt::log_err`], but logs to `logger` instead of the facade, e.g. a
+    /// [`memory_sink::MemorySink`] or a backend that isn't installed.
+    fn log_err_to(self, logger: &dyn log::Log) -> Self;
+
+    /// Same as [`ResultExt::log_err_msg`], but logs to `logger` instead of the facade.
+    fn log_err_msg_to(self, logger: &dyn log::Log, context: &str) -> Self;
+}
+
+impl<T, E> ResultExt for Result<T, E>
+where
+    E: std::error::Error,
+{
+    fn log_err(self) -> Self {
+        if let Err(e) = &self {
+            log::error!("{}", source_chain(e));
+        }
+        self
+    }
+
+    fn log_err_msg(self, context: &str) -> Self {
+        if let Err(e) = &self {
+            log::error!("{}: {}", context, source_chain(e));
+        }
+        self
+    }
+
+    fn log_err_to(self, logger: &dyn log::Log) -> Self {
+        if let Err(e) = &self {
+            log_error_to(logger, format_args!("{}", source_chain(e)));
+        }
+        self
+    }
+
+    fn log_err_msg_to(self, logger: &dyn log::Log, context: &str) -> Self {
+        if let Err(e) = &self {
+            log_error_to(logger, format_args!("{}: {}", context, source_chain(e)));
+        }
+        self
+    }
+}
+
+// An Error record of the same target as the log::error! of the facade versions
+fn log_error_to(logger: &dyn log::Log, args: std::fmt::Arguments<'_>) {
+    let metadata = log::Metadata::builder()
+        .level(log::Level::Error)
+        .target(module_path!())
+        .build();
+    if logger.enabled(&metadata) {
+        logger.log(
+            &log::Record::builder()
+                .metadata(metadata)
+                .args(args)
+                .module_path_static(Some(module_path!()))
+                .file_static(Some(file!()))
+                .line(Some(line!()))
+                .build(),
+        );
+    }
+}
+
+/// Logs the `None`s of an `Option` through the log crate facade and passes the `Option` on
+/// unchanged, e.g. in a filter pipeline:
+/// `values.filter_map(|value| bar(value).log_none(LogLevel::Debug, "value skipped"))`.
+pub trait OptionExt {
+    /// Logs `message` at `level` if the option is `None`.
+    fn log_none(self, level: LogLevel, message: &str) -> Self;
+}
+
+impl<T> OptionExt for Option<T> {
+    fn log_none(self, level: LogLevel, message: &str) -> Self {
+        if self.is_none() {
+            log::log!(log::Level::from(level), "{}", message);
+        }
+        self
+    }
+}
+
+/// Logs `error` and its sources as a single Error record of key=value fields through the log
+/// crate facade, e.g. `error="config invalid" cause.1="file missing" backtrace="..."`.
+///
+/// For an `anyhow::Error` pass `err.as_ref()`, its contexts are part of the sources. The backtrace
+/// is captured here if enabled with `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`, it's left out
+/// otherwise.
+pub fn log_error_chain(error: &dyn std::error::Error) {
+    log::error!("{}", error_chain_fields(error, &Backtrace::capture()));
+}
+
+fn error_chain_fields(error: &dyn std::error::Error, backtrace: &Backtrace) -> String {
+    let mut fields = String::new();
+    for (i, error) in sources(error).enumerate() {
+        if i == 0 {
+            let _ = write!(fields, "error={:?}", error.to_string());
+        } else {
+            let _ = write!(fields, " cause.{}={:?}", i, error.to_string());
+        }
+    }
+    if backtrace.status() == BacktraceStatus::Captured {
+        let _ = write!(fields, " backtrace={:?}", backtrace.to_string());
+    }
+    fields
+}
+
+// The error followed by its sources
+fn sources(error: &dyn std::error::Error) -> impl Iterator<Item = &dyn std::error::Error> {
+    std::iter::successors(Some(error), |error| error.source())
+}
+
+// The error and its sources, separated by colons
+fn source_chain(error: &dyn std::error::Error) -> String {
+    sources(error)
+        .map(|error| error.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+pub fn run() {
+    let s_slice = "String slice";
+    let s_owned = String::from("Owned String");
+
+    if let Err(e) = write_to_log(
+        LogType::FileSystem,
+        LogLevel::Info,
+        "Another one string slice",
+    ) {
+        eprintln!("Logging failed with error: {:?}", e);
+    }
+    let _ = write_to_log(LogType::Console, LogLevel::Debug, &s_owned); // Just suppress error message
+    write_to_log(LogType::Console, LogLevel::Info, s_slice)
+        .expect("Non-recoverable error: Logging failed");
+
+    // Other ways to create a string in Rust. Will require more complex implementation of the write_to_log function
+    // let s_pathbuf = PathBuf::from("some/path");
+    // let s_path = Path::new("some/path");
+    // let s_vec_of_u8 = vec!['a', 'b', 'c', 'd', 'e'];
+    // let s_slice_of_u8 = &['a', 'b', 'c', 'd', 'e'];
+    // let s_osstring = OsString::from("some/path");
+
+    external_log::write_to_log(s_slice);
+    external_log::write_to_log(s_owned);
+    external_log::write_to_log("string slice again");
+}
+
+// Most tests write Info records, which a release build with a release_max_level_* feature
+// compiles out
+#[cfg(all(
+    test,
+    not(all(
+        not(debug_assertions),
+        any(
+            feature = "release_max_level_off",
+            feature = "release_max_level_error",
+            feature = "release_max_level_warn"
+        )
+    ))
+))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_log_level_from_log_crate() {
+        assert_eq!(LogLevel::from(log::Level::Error), LogLevel::Error);
+        assert_eq!(LogLevel::from(log::Level::Warn), LogLevel::Warn);
+        assert_eq!(LogLevel::from(log::Level::Info), LogLevel::Info);
+        assert_eq!(LogLevel::from(log::Level::Debug), LogLevel::Debug);
+        assert_eq!(LogLevel::from(log::Level::Trace), LogLevel::Debug);
+    }
+
+    #[test]
+    fn test_log_backend_target_mapping() {
+        use log::Log;
+
+        let backend = log_backend::LogBackend::new(log::LevelFilter::Info, LogType::Console)
+            .with_target("my_crate", LogType::FileSystem)
+            .with_target("my_crate::net", LogType::Network);
+
+        assert_eq!(backend.log_type_for("other"), LogType::Console);
+        assert_eq!(backend.log_type_for("my_crate::db"), LogType::FileSystem);
+        assert_eq!(backend.log_type_for("my_crate::net::tcp"), LogType::Network);
+
+        let debug = log::Metadata::builder().level(log::Level::Debug).build();
+        let warn = log::Metadata::builder().level(log::Level::Warn).build();
+        assert!(!backend.enabled(&debug));
+        assert!(backend.enabled(&warn));
+    }
+
+    #[test]
+    fn test_log_startup_info() {
+        let dir = test_dir("startup_info");
+        let options = capped_options(&dir, 1024, OnFull::Rotate { keep: 3 });
+        let backend = log_backend::LogBackend::new(log::LevelFilter::Debug, LogType::FileSystem)
+            .with_color(log_backend::ColorChoice::Never)
+            .with_target("my_crate::net", LogType::FileSystem)
+            .with_options(options.clone());
+
+        backend.log_startup_info().unwrap();
+
+        let content = fs::read_to_string(&options.file_path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            format!(
+                "[INFO] nxlog_task: startup version={} max_level=DEBUG format=Plain \
+                 default_log_type=FileSystem colors=false",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(
+            lines[1],
+            "[INFO] nxlog_task: startup target=my_crate::net log_type=FileSystem"
+        );
+        assert!(lines[2].contains(
+            r#"max_file_size="Some(FileSizeCap { max_bytes: 1024, on_full: Rotate { keep: 3 } })""#
+        ));
+        assert_eq!(lines.len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_network_log_type_is_unsupported() {
+        use log::Log;
+
+        let result = write_to_log(LogType::Network, LogLevel::Info, "Test log message");
+        assert!(matches!(
+            result,
+            Err(LogError::Unsupported(LogType::Network))
+        ));
+
+        let backend = log_backend::LogBackend::new(log::LevelFilter::Info, LogType::Network)
+            .with_color(log_backend::ColorChoice::Never);
+        assert!(matches!(
+            backend.log_startup_info(),
+            Err(LogError::Unsupported(LogType::Network))
+        ));
+
+        // The error is reported, not panicked on
+        backend.log(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("my_crate::net")
+                .args(format_args!("connection lost"))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        use log::Log;
+
+        let dir = test_dir("mock_clock");
+        let options = LogOptions {
+            file_path: dir.join("log.txt"),
+            ..LogOptions::default()
+        };
+        let clock =
+            clock::MockClock::new(std::time::UNIX_EPOCH + Duration::from_secs(1_696_161_600));
+        let backend = log_backend::LogBackend::new(log::LevelFilter::Info, LogType::FileSystem)
+            .with_format(log_backend::Format::EnvLogger)
+            .with_clock(clock.clone())
+            .with_options(options.clone());
+        for _ in 0..2 {
+            backend.log(
+                &log::Record::builder()
+                    .args(format_args!("tick"))
+                    .level(log::Level::Info)
+                    .target("my_crate")
+                    .build(),
+            );
+            clock.advance(Duration::from_secs(90));
+        }
+
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[2023-10-01T12:00:00Z INFO  my_crate] tick\n[2023-10-01T12:01:30Z INFO  my_crate] tick\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_memory_sink() {
+        use log::Log;
+
+        let start = std::time::UNIX_EPOCH + Duration::from_secs(1_696_161_600);
+        let clock = clock::MockClock::new(start);
+        let sink =
+            memory_sink::MemorySink::new(log::LevelFilter::Info, 3).with_clock(clock.clone());
+        let log = |level, message: &str| {
+            sink.log(
+                &log::Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .target("my_crate")
+                    .build(),
+            );
+            clock.advance(Duration::from_secs(1));
+        };
+
+        log(log::Level::Info, "dropped as the oldest");
+        log(log::Level::Debug, "filtered out");
+        log(log::Level::Warn, "disk almost full");
+        log(log::Level::Info, "request served");
+        log(log::Level::Error, "disk full");
+
+        let messages = |records: Vec<memory_sink::MemoryRecord>| {
+            records
+                .into_iter()
+                .map(|record| record.message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sink.len(), 3);
+        assert_eq!(messages(sink.by_level(LogLevel::Info)), ["request served"]);
+        assert_eq!(
+            messages(sink.containing("disk")),
+            ["disk almost full", "disk full"]
+        );
+        assert_eq!(
+            messages(sink.between(
+                start + Duration::from_secs(3),
+                start + Duration::from_secs(4)
+            )),
+            ["request served"]
+        );
+
+        sink.clear();
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn test_format_json() {
+        use log::Log;
+
+        let time = std::time::UNIX_EPOCH + Duration::from_secs(1_696_161_600);
+        assert_eq!(
+            log_backend::format_json(time, log::Level::Warn, "my_crate", "say \"hi\"\n\x1b"),
+            r#"{"time":"2023-10-01T12:00:00Z","level":"WARN","target":"my_crate","message":"say \"hi\"\n\u001b"}"#
+        );
+        assert_eq!(
+            log_backend::format_json(
+                time,
+                log::Level::Warn,
+                "my_crate",
+                "\u{85}\u{9b}\u{202e}\u{2069}é"
+            ),
+            r#"{"time":"2023-10-01T12:00:00Z","level":"WARN","target":"my_crate","message":"\u0085\u009b\u202e\u2069é"}"#
+        );
+
+        let dir = test_dir("format_json");
+        let options = LogOptions {
+            file_path: dir.join("log.ndjson"),
+            anonymize: vec![Anonymize::MaskEmails],
+            ..LogOptions::default()
+        };
+        let backend = log_backend::LogBackend::new(log::LevelFilter::Info, LogType::FileSystem)
+            .with_format(log_backend::Format::Json)
+            .with_options(options.clone());
+        for message in ["first by ann@example.com", "multi\nline \"quoted\""] {
+            backend.log(
+                &log::Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(log::Level::Info)
+                    .target("my_crate::db")
+                    .build(),
+            );
+        }
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&options.file_path)
+            .unwrap()
+            .write_all(b"\nnot json\n")
+            .unwrap();
+
+        let file = BufReader::new(fs::File::open(&options.file_path).unwrap());
+        let records = log_backend::read_json_lines(file).collect::<Vec<_>>();
+        assert_eq!(records.len(), 3);
+        let first = records[0].as_ref().unwrap();
+        assert_eq!(
+            (
+                first.level.as_str(),
+                first.target.as_str(),
+                first.message.as_str()
+            ),
+            ("INFO", "my_crate::db", "first by a***@example.com")
+        );
+        assert!(humantime::parse_rfc3339(&first.time).is_ok());
+        assert_eq!(
+            records[1].as_ref().unwrap().message,
+            "multi\nline \"quoted\""
+        );
+        assert!(records[2].is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_env_logger() {
+        let line = log_backend::format_env_logger(
+            std::time::UNIX_EPOCH,
+            log::Level::Info,
+            "my_crate::db",
+            &"connected",
+            &log_backend::Theme::PLAIN,
+        );
+
+        assert_eq!(line, "[1970-01-01T00:00:00Z INFO  my_crate::db] connected");
+    }
+
+    #[test]
+    fn test_format_with_theme() {
+        use log_backend::{Style, Theme};
+
+        let theme = Theme {
+            error: Style("31"),
+            target: Style("1"),
+            ..Theme::PLAIN
+        };
+
+        let line = log_backend::format_plain(LogLevel::Error, "db", &"failed", &theme);
+        assert_eq!(line, "\x1b[31m[ERROR]\x1b[0m \x1b[1mdb\x1b[0m: failed");
+
+        let line = log_backend::format_plain(LogLevel::Info, "db", &"ok", &Theme::PLAIN);
+        assert_eq!(line, "[INFO] db: ok");
+
+        let line = log_backend::format_env_logger(
+            std::time::UNIX_EPOCH,
+            log::Level::Warn,
+            "db",
+            &"slow",
+            &Theme {
+                warn: Style("33"),
+                ..Theme::PLAIN
+            },
+        );
+        assert_eq!(line, "[1970-01-01T00:00:00Z \x1b[33mWARN \x1b[0m db] slow");
+    }
+
+    #[test]
+    fn test_multi_line_join() {
+        let message = "first\nsecond\r\nthird";
+
+        assert_eq!(
+            MultiLine::Raw.join("[INFO]", message),
+            format!("[INFO] {}", message)
+        );
+        assert_eq!(
+            MultiLine::Indent.join("[INFO]", message),
+            "[INFO] first\n    second\n    third"
+        );
+        assert_eq!(
+            MultiLine::Escape.join("[INFO]", message),
+            "[INFO] first\\nsecond\\nthird"
+        );
+        assert_eq!(
+            MultiLine::Frame.join("[INFO]", message),
+            "[INFO] >>>\nfirst\nsecond\nthird\n[INFO] <<<"
+        );
+
+        // Trailing newlines are kept
+        assert_eq!(MultiLine::Escape.join("[INFO]", "a\n"), "[INFO] a\\n");
+        assert_eq!(
+            MultiLine::Escape.join("[INFO]", "a\r\n\n"),
+            "[INFO] a\\n\\n"
+        );
+        assert_eq!(
+            MultiLine::Indent.join("[INFO]", "a\n\n"),
+            "[INFO] a\n    \n    "
+        );
+        assert_eq!(
+            MultiLine::Frame.join("[INFO]", "a\n"),
+            "[INFO] >>>\na\n\n[INFO] <<<"
+        );
+
+        // Single-line messages are the same in every mode
+        for multi_line in [
+            MultiLine::Raw,
+            MultiLine::Indent,
+            MultiLine::Escape,
+            MultiLine::Frame,
+        ] {
+            assert_eq!(multi_line.join("[INFO]", "single"), "[INFO] single");
+        }
+    }
+
+    fn format_info(options: &LogOptions, message: &str) -> String {
+        let mut formatted = String::new();
+        format_record_into(options, &mut formatted, LogLevel::Info, message);
+        formatted
+    }
+
+    #[test]
+    fn test_record_buffer_reuse() {
+        let options = LogOptions::default();
+        let first = with_record_buffer(|buffer| {
+            format_record_into(&options, buffer, LogLevel::Warn, "first");
+            assert_eq!(buffer, "[WARN] first");
+            buffer.as_ptr()
+        });
+
+        // The same allocation is handed out again, empty, and a nested call gets its own
+        with_record_buffer(|buffer| {
+            assert!(buffer.is_empty());
+            assert_eq!(buffer.as_ptr(), first);
+
+            format_record_into(&options, buffer, LogLevel::Error, "outer");
+            with_record_buffer(|nested| {
+                format_record_into(&options, nested, LogLevel::Debug, "nested");
+                assert_eq!(nested, "[DEBUG] nested");
+            });
+            assert_eq!(buffer, "[ERROR] outer");
+        });
+    }
+
+    #[test]
+    fn test_escape_control_chars() {
+        assert!(matches!(
+            escape_control_chars("plain\r\ntext\n"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            escape_control_chars("\x1b[31mred\x1b[0m\rfake\u{202E}"),
+            "\\u{1b}[31mred\\u{1b}[0m\\u{d}fake\\u{202e}"
+        );
+
+        // A forged record ends up indented, after the escaped carriage return
+        let options = LogOptions::default();
+        assert_eq!(
+            format_info(&options, "user\r\n[ERROR] forged"),
+            "[INFO] user\n    [ERROR] forged"
+        );
+        assert_eq!(format_info(&options, "a\rb"), "[INFO] a\\u{d}b");
+    }
+
+    #[test]
+    fn test_anonymize() {
+        let salt = "secret".to_string();
+        let hash = |address: &str| hash_ip_address(&salt, address.parse().unwrap());
+        let transforms = [
+            Anonymize::HashIpAddresses { salt: salt.clone() },
+            Anonymize::MaskEmails,
+            Anonymize::DropKeys(vec!["token".to_string(), "card".to_string()]),
+        ];
+        let anonymized = |message| anonymize(message, &transforms);
+
+        assert!(matches!(
+            anonymized("nothing here at 12:30:00"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            anonymized("from 192.168.0.12:51234 and [::1]:80, not 999.1.1.1"),
+            format!(
+                "from {}:51234 and [{}]:80, not 999.1.1.1",
+                hash("192.168.0.12"),
+                hash("::1")
+            )
+        );
+        assert_eq!(
+            anonymized("client_ip:2001:db8::1 user:fe80::1 ok"),
+            format!(
+                "client_ip:{} user:{} ok",
+                hash("2001:db8::1"),
+                hash("fe80::1")
+            )
+        );
+        assert_eq!(
+            anonymized("peer [2001:db8::2]:443 via fe80::1%eth0, from ::ffff:10.0.0.1."),
+            format!(
+                "peer [{}]:443 via {}%eth0, from {}.",
+                hash("2001:db8::2"),
+                hash("fe80::1"),
+                hash("::ffff:10.0.0.1")
+            )
+        );
+        assert_eq!(hash("10.0.0.1"), hash("10.0.0.1"));
+        assert_ne!(hash("10.0.0.1"), hash("10.0.0.2"));
+
+        assert_eq!(
+            anonymized("login john.doe@example.co.uk ok"),
+            "login j***@example.co.uk ok"
+        );
+        assert_eq!(
+            anonymized(r#"user=7 token=abc card="4111 1111 \" x" status=ok"#),
+            "user=7 status=ok"
+        );
+        assert_eq!(anonymized("token=abc"), "");
+        assert_eq!(anonymized("token=abc  status=ok"), "status=ok");
+        assert_eq!(anonymized("tokens=abc"), "tokens=abc");
+
+        let options = LogOptions {
+            anonymize: vec![Anonymize::MaskEmails],
+            ..LogOptions::default()
+        };
+        assert_eq!(
+            format_info(&options, "by ann@example.com"),
+            "[INFO] by a***@example.com"
+        );
+    }
+
+    // A fresh directory for the files of a single test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nxlog_task_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create test directory");
+        dir
+    }
+
+    fn capped_options(dir: &Path, max_bytes: u64, on_full: OnFull) -> LogOptions {
+        LogOptions {
+            file_path: dir.join("log.txt"),
+            max_file_size: Some(FileSizeCap { max_bytes, on_full }),
+            ..LogOptions::default()
+        }
+    }
+
+    // Every record is "[INFO] n\n", i.e. 9 bytes
+    fn write_records(options: &LogOptions, count: usize) {
+        for i in 0..count {
+            write_to_log_with(options, LogType::FileSystem, LogLevel::Info, i.to_string())
+                .expect("Failed to write to log");
+        }
+    }
+
+    #[test]
+    fn test_file_size_cap_stop() {
+        let dir = test_dir("cap_stop");
+        let options = capped_options(&dir, 20, OnFull::Stop);
+
+        write_records(&options, 5);
+
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 0\n[INFO] 1\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_size_cap_overwrite() {
+        let dir = test_dir("cap_overwrite");
+        let options = capped_options(&dir, 20, OnFull::Overwrite);
+
+        write_records(&options, 5);
+
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 4\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_size_cap_rotate() {
+        let dir = test_dir("cap_rotate");
+        let options = capped_options(&dir, 20, OnFull::Rotate { keep: 2 });
+
+        write_records(&options, 7);
+
+        let read = |index| fs::read_to_string(rotated_path(&options.file_path, index)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 6\n"
+        );
+        assert_eq!(read(1), "[INFO] 4\n[INFO] 5\n");
+        assert_eq!(read(2), "[INFO] 2\n[INFO] 3\n");
+        assert!(!rotated_path(&options.file_path, 3).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    fn guarded_options(dir: &Path, on_low: OnLowDiskSpace) -> LogOptions {
+        LogOptions {
+            file_path: dir.join("log.txt"),
+            // Never enough free space
+            disk_space_guard: Some(DiskSpaceGuard {
+                min_free_bytes: u64::MAX,
+                on_low,
+            }),
+            ..LogOptions::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_low_disk_space_drop_verbose() {
+        let dir = test_dir("disk_drop_verbose");
+        let options = guarded_options(&dir, OnLowDiskSpace::DropVerbose);
+
+        for level in [
+            LogLevel::Debug,
+            LogLevel::Info,
+            LogLevel::Warn,
+            LogLevel::Error,
+        ] {
+            write_to_log_with(&options, LogType::FileSystem, level, "msg").unwrap();
+        }
+
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[WARN] msg\n[ERROR] msg\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_low_disk_space_warn_once() {
+        let dir = test_dir("disk_warn");
+        let options = guarded_options(&dir, OnLowDiskSpace::Warn);
+
+        let other = LogOptions {
+            file_path: dir.join("other.txt"),
+            ..options.clone()
+        };
+
+        write_records(&options, 2);
+        write_records(&other, 1);
+
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            format!(
+                "[WARN] Free disk space is below {} bytes\n[INFO] 0\n[INFO] 1\n",
+                u64::MAX
+            )
+        );
+        // Every log file gets its own warning
+        assert_eq!(
+            fs::read_to_string(&other.file_path).unwrap(),
+            format!(
+                "[WARN] Free disk space is below {} bytes\n[INFO] 0\n",
+                u64::MAX
+            )
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_low_disk_space_warning_is_a_record() {
+        let dir = test_dir("disk_warn_record");
+        let warning = format!("[WARN] Free disk space is below {} bytes\n", u64::MAX);
+
+        // Counted in the receipt of the record it's written before
+        let options = guarded_options(&dir, OnLowDiskSpace::Warn);
+        let written =
+            write_to_log_with(&options, LogType::FileSystem, LogLevel::Info, "0").unwrap();
+        assert_eq!(written.bytes, warning.len() + "[INFO] 0\n".len());
+
+        // Within the size cap like any other record
+        let capped = LogOptions {
+            file_path: dir.join("capped.txt"),
+            max_file_size: Some(FileSizeCap {
+                max_bytes: 20,
+                on_full: OnFull::Stop,
+            }),
+            ..options.clone()
+        };
+        let written = write_to_log_with(&capped, LogType::FileSystem, LogLevel::Info, "0").unwrap();
+        assert!(written.dropped);
+        assert_eq!(fs::read_to_string(&capped.file_path).unwrap(), "[INFO] 0\n");
+
+        // In the format of the records
+        let json = LogOptions {
+            file_path: dir.join("log.ndjson"),
+            ..options.clone()
+        };
+        let backend = log_backend::LogBackend::new(log::LevelFilter::Info, LogType::FileSystem)
+            .with_format(log_backend::Format::Json)
+            .with_options(json.clone());
+        fixture::RecordFixture::new("first").log(&backend);
+        let content = fs::read_to_string(&json.file_path).unwrap();
+        let records = log_backend::read_json_lines(content.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].level, "WARN");
+        assert_eq!(records[0].message, warning[7..warning.len() - 1]);
+        assert_eq!(records[1].message, "first");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_low_disk_space_purge_rotated() {
+        let dir = test_dir("disk_purge");
+        let options = guarded_options(&dir, OnLowDiskSpace::PurgeRotated);
+        for index in 1..=3 {
+            fs::write(rotated_path(&options.file_path, index), "old").unwrap();
+        }
+
+        write_records(&options, 1);
+
+        assert!(!rotated_path(&options.file_path, 1).exists());
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 0\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn test_available_space() {
+        assert!(available_space(Path::new("log.txt")).is_some_and(|free| free > 0));
+    }
+
+    #[test]
+    fn test_sync_policy() {
+        assert!(!SyncPolicy::Never.syncs_after(LogLevel::Error, true));
+        assert!(SyncPolicy::PerBatch.syncs_after(LogLevel::Info, true));
+        assert!(!SyncPolicy::PerBatch.syncs_after(LogLevel::Error, false));
+        assert!(SyncPolicy::PerRecord.syncs_after(LogLevel::Debug, false));
+        assert!(SyncPolicy::PerError.syncs_after(LogLevel::Error, false));
+        assert!(!SyncPolicy::PerError.syncs_after(LogLevel::Warn, true));
+
+        let dir = test_dir("sync_per_record");
+        let options = LogOptions {
+            file_path: dir.join("log.txt"),
+            sync_policy: SyncPolicy::PerRecord,
+            ..LogOptions::default()
+        };
+        write_records(&options, 2);
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 0\n[INFO] 1\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_appends_keep_lines_intact() {
+        let dir = test_dir("concurrent_appends");
+        let options = LogOptions {
+            file_path: dir.join("log.txt"),
+            ..LogOptions::default()
+        };
+        let message = "x".repeat(1000);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        write_to_log_with(&options, LogType::FileSystem, LogLevel::Info, &message)
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let content = fs::read_to_string(&options.file_path).unwrap();
+        assert_eq!(content.lines().count(), 800);
+        assert!(content
+            .lines()
+            .all(|line| line == format!("[INFO] {}", message)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_platform_path_extended_length() {
+        let extended = |path| platform_path(Path::new(path)).into_owned();
+
+        assert_eq!(
+            extended(r"C:\logs/log.txt"),
+            Path::new(r"\\?\C:\logs\log.txt")
+        );
+        assert_eq!(
+            extended(r"\\server\share\log.txt"),
+            Path::new(r"\\?\UNC\server\share\log.txt")
+        );
+        assert_eq!(extended(r"\\?\C:\log.txt"), Path::new(r"\\?\C:\log.txt"));
+        assert_eq!(extended("log.txt"), Path::new("log.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_and_dir_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("modes");
+        let options = LogOptions {
+            file_path: dir.join("nested").join("log.txt"),
+            file_mode: Some(0o600),
+            dir_mode: Some(0o700),
+            max_file_size: Some(FileSizeCap {
+                max_bytes: 10,
+                on_full: OnFull::Rotate { keep: 1 },
+            }),
+            ..LogOptions::default()
+        };
+
+        // The second record rotates the file, so the new file is created with the mode too
+        write_records(&options, 2);
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir.join("nested")), 0o700);
+        assert_eq!(mode(&options.file_path), 0o600);
+        assert_eq!(mode(&rotated_path(&options.file_path, 1)), 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = FileOwner::from_names(Some("root"), None).unwrap();
+        assert_eq!(
+            root,
+            FileOwner {
+                uid: Some(0),
+                gid: None
+            }
+        );
+        assert!(FileOwner::from_names(Some("no-such-user-nxlog"), None).is_err());
+
+        // Only root can give the files away
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let dir = test_dir("owner");
+        let owner = FileOwner {
+            uid: Some(65534),
+            gid: Some(65534),
+        };
+        let options = LogOptions {
+            file_path: dir.join("nested").join("log.txt"),
+            owner: Some(owner),
+            ..LogOptions::default()
+        };
+
+        write_records(&options, 1);
+
+        for path in [dir.join("nested"), options.file_path.clone()] {
+            let metadata = fs::metadata(path).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (65534, 65534));
+        }
+        // The already existing test directory is left alone
+        assert_eq!(fs::metadata(&dir).unwrap().uid(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_total_size_retention() {
+        let dir = test_dir("retention");
+        let options = LogOptions {
+            max_total_size: Some(40),
+            ..capped_options(&dir, 20, OnFull::Rotate { keep: 10 })
+        };
+
+        write_records(&options, 10);
+
+        // 18 bytes per rotated file, only one fits next to the 20 bytes reserved for the current file
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 8\n[INFO] 9\n"
+        );
+        assert!(rotated_path(&options.file_path, 1).exists());
+        assert!(!rotated_path(&options.file_path, 2).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_external_rotation() {
+        let dir = test_dir("external_rotation");
+        let options = LogOptions {
+            file_path: dir.join("log.txt"),
+            ..LogOptions::default()
+        };
+        let path = &options.file_path;
+        let moved = dir.join("log.txt.old");
+
+        // Renamed away, like logrotate does before its postrotate script
+        write_records(&options, 2);
+        fs::rename(path, &moved).unwrap();
+        write_records(&options, 1);
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "[INFO] 0\n[INFO] 1\n");
+        assert_eq!(fs::read_to_string(path).unwrap(), "[INFO] 0\n");
+
+        // Deleted
+        fs::remove_file(path).unwrap();
+        write_records(&options, 1);
+        assert_eq!(fs::read_to_string(path).unwrap(), "[INFO] 0\n");
+
+        // Copied and truncated in place, the next record starts at the beginning again
+        write_records(&options, 2);
+        fs::copy(path, &moved).unwrap();
+        fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        write_records(&options, 1);
+        assert_eq!(fs::read_to_string(path).unwrap(), "[INFO] 0\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let dir = test_dir("heartbeat");
+        let options = LogOptions {
+            file_path: dir.join("log.txt"),
+            ..LogOptions::default()
+        };
+
+        let heartbeat = start_heartbeat(
+            options.clone(),
+            LogType::FileSystem,
+            Duration::from_millis(10),
+        );
+        thread::sleep(Duration::from_millis(100));
+        drop(heartbeat);
+
+        // No heartbeat is written once the guard is dropped
+        let content = fs::read_to_string(&options.file_path).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(fs::read_to_string(&options.file_path).unwrap(), content);
+
+        assert!(content.lines().count() >= 2);
+        for line in content.lines() {
+            assert!(line.starts_with("[INFO] alive uptime_secs=0 dropped_records="));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_records() {
+        let dir = test_dir("dropped_records");
+        let options = capped_options(&dir, 20, OnFull::Stop);
+
+        // Other tests drop records too, so only the growth is checked
+        let before = dropped_records();
+        write_records(&options, 5);
+        assert!(dropped_records() >= before + 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_written_receipt() {
+        let dir = test_dir("written_receipt");
+        let mut options = capped_options(&dir, 28, OnFull::Stop);
+        let write = |options: &LogOptions, level| {
+            write_to_log_with(options, LogType::FileSystem, level, "0").unwrap()
+        };
+
+        assert_eq!(
+            write(&options, LogLevel::Info),
+            Written {
+                bytes: 9,
+                dropped: false,
+                synced: false,
+            }
+        );
+
+        options.sync_policy = SyncPolicy::PerError;
+        assert!(!write(&options, LogLevel::Info).synced);
+        assert!(write(&options, LogLevel::Error).synced);
+
+        // The file is full
+        assert_eq!(
+            write(&options, LogLevel::Info),
+            Written {
+                bytes: 0,
+                dropped: true,
+                synced: false,
+            }
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_all_to_log() {
+        let dir = test_dir("write_all_to_log");
+        let mut options = LogOptions {
+            file_path: dir.join("log.txt"),
+            sync_policy: SyncPolicy::PerBatch,
+            ..LogOptions::default()
+        };
+
+        let written =
+            write_all_to_log_with(&options, LogType::FileSystem, LogLevel::Info, ["0", "1"])
+                .unwrap();
+        assert_eq!(written.bytes, 18);
+        assert!(written.synced);
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 0\n[INFO] 1\n"
+        );
+
+        // Capped records are checked one by one, the file has room for one more
+        options.max_file_size = Some(FileSizeCap {
+            max_bytes: 27,
+            on_full: OnFull::Stop,
+        });
+        let written =
+            write_all_to_log_with(&options, LogType::FileSystem, LogLevel::Info, ["2", "3"])
+                .unwrap();
+        assert_eq!(written.bytes, 9);
+        assert!(written.dropped);
+        assert!(written.synced);
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            "[INFO] 0\n[INFO] 1\n[INFO] 2\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        // The default options, without a file shared with the other tests
+        let written = write_all_to_log(LogType::Console, LogLevel::Info, ["0", "1"]).unwrap();
+        assert_eq!(written.bytes, 18);
+        assert!(write_all_to_log(LogType::Network, LogLevel::Info, ["0"]).is_err());
+    }
+
+    #[test]
+    fn test_internal_error_rate_limit() {
+        static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        set_internal_error_sink(|message| REPORTED.lock().unwrap().push(message.to_string()));
+
+        let error = LogError::Io {
+            path: None,
+            source: std::io::Error::other("disk full"),
+        };
+        let start = Instant::now();
+        for _ in 0..15 {
+            report_internal_error_at(&error, start);
+        }
+        report_internal_error_at(&error, start + Duration::from_secs(1));
+        set_internal_error_sink(print_internal_error);
+
+        let reported = REPORTED.lock().unwrap();
+        let expected = format!("Logging failed with error: {}", error);
+        assert_eq!(reported.len(), 12);
+        assert!(reported[..10].iter().all(|message| *message == expected));
+        assert_eq!(reported[10], "5 more logging errors suppressed");
+        assert_eq!(reported[11], expected);
+    }
+
+    #[test]
+    fn test_hash_chain() {
+        let dir = test_dir("hash_chain");
+        let options = LogOptions {
+            hash_chain: true,
+            ..capped_options(&dir, 1024, OnFull::Rotate { keep: 1 })
+        };
+        let path = &options.file_path;
+
+        write_records(&options, 3);
+        write_to_log_with(
+            &LogOptions {
+                multi_line: MultiLine::Frame,
+                ..options.clone()
+            },
+            LogType::FileSystem,
+            LogLevel::Info,
+            "multi\nline",
+        )
+        .unwrap();
+        assert_eq!(hash_chain::verify(path).unwrap(), 4);
+
+        let content = fs::read_to_string(path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("[INFO] 0 #"));
+        assert_eq!(lines[0].len(), "[INFO] 0 #".len() + 64);
+
+        let tampered = [
+            content.replacen("[INFO] 1", "[INFO] 7", 1),
+            content.replacen(&format!("{}\n", lines[1]), "", 1),
+            content.replacen(&format!("{}\n", lines[0]), &format!("{}\n", lines[1]), 1),
+            content[..content.len() - 10].to_string(),
+            format!("{}forged\n", content),
+        ];
+        for content in tampered {
+            fs::write(path, content).unwrap();
+            assert!(hash_chain::verify(path).is_err());
+        }
+
+        // Records keep relying on the last hash, so appending to a tampered file fails too
+        assert!(write_to_log_with(&options, LogType::FileSystem, LogLevel::Info, "next").is_err());
+
+        // A rotated file and the new one are verified separately
+        fs::remove_file(path).unwrap();
+        write_records(&options, 20);
+        let rotated = rotated_path(path, 1);
+        assert!(hash_chain::verify(&rotated).unwrap() > 0);
+        assert!(hash_chain::verify(path).unwrap() > 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_hash_chain_json() {
+        let dir = test_dir("hash_chain_json");
+        let options = LogOptions {
+            file_path: dir.join("log.ndjson"),
+            hash_chain: true,
+            ..LogOptions::default()
+        };
+        let backend = log_backend::LogBackend::new(log::LevelFilter::Info, LogType::FileSystem)
+            .with_format(log_backend::Format::Json)
+            .with_clock(fixture::clock())
+            .with_options(options.clone());
+        for message in ["first", "multi\nline \"quoted\"", "{}"] {
+            fixture::RecordFixture::new(message).log(&backend);
+        }
+        assert_eq!(hash_chain::verify(&options.file_path).unwrap(), 3);
+
+        let content = fs::read_to_string(&options.file_path).unwrap();
+        let records = log_backend::read_json_lines(content.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records[1].message, "multi\nline \"quoted\"");
+        let first = content.lines().next().unwrap();
+        assert!(first.starts_with(r#"{"time":"2023-10-01T12:00:00Z","level":"INFO""#));
+        assert!(first.contains(r#""message":"first","hash":""#));
+
+        fs::write(
+            &options.file_path,
+            content.replacen(r#""message":"first""#, r#""message":"forged""#, 1),
+        )
+        .unwrap();
+        assert!(hash_chain::verify(&options.file_path).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_hash_chain() {
+        let dir = test_dir("concurrent_hash_chain");
+        let options = LogOptions {
+            file_path: dir.join("log.txt"),
+            hash_chain: true,
+            ..LogOptions::default()
+        };
+
+        // Every record must be chained to the one right before it in the file
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let options = &options;
+                scope.spawn(move || {
+                    for line in 0..50 {
+                        let message = format!("thread {} line {}", thread, line);
+                        write_to_log_with(options, LogType::FileSystem, LogLevel::Info, message)
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(hash_chain::verify(&options.file_path).unwrap(), 400);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_signed_rotation() {
+        let dir = test_dir("signed_rotation");
+        let key = signature::SigningKey::from_bytes(&[7; 32]);
+        let options = LogOptions {
+            hash_chain: true,
+            signing_key: Some(key.clone()),
+            ..capped_options(&dir, 200, OnFull::Rotate { keep: 2 })
+        };
+
+        write_records(&options, 5);
+        let rotated = rotated_path(&options.file_path, 1);
+        assert!(signature::verify(&rotated, &key.verifying_key()).is_ok());
+        // The signature doesn't get in the way of the chain, the current file isn't signed yet
+        assert_eq!(hash_chain::verify(&rotated).unwrap(), 2);
+        assert!(signature::verify(&options.file_path, &key.verifying_key()).is_err());
+
+        let other_key = signature::SigningKey::from_bytes(&[8; 32]);
+        assert!(signature::verify(&rotated, &other_key.verifying_key()).is_err());
+
+        let content = fs::read_to_string(&rotated).unwrap();
+        fs::write(&rotated, content.replacen("[INFO] ", "[WARN] ", 1)).unwrap();
+        assert!(signature::verify(&rotated, &key.verifying_key()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_access_log() {
+        use access_log::AccessRecord;
+        use std::time::UNIX_EPOCH;
+
+        let record = AccessRecord {
+            client: "127.0.0.1",
+            user: Some("frank"),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            method: "GET",
+            path: "/apache_pb.gif",
+            protocol: "HTTP/1.0",
+            status: 200,
+            bytes: Some(2326),
+            referer: Some("http://www.example.com/start.html"),
+            user_agent: Some("Mozilla/4.08 [en] (Win98; I ;Nav)"),
+            latency: None,
+        };
+        let formatted = |record: &AccessRecord| {
+            let mut line = String::new();
+            access_log::format_combined(record, &mut line);
+            line
+        };
+
+        assert_eq!(
+            formatted(&record),
+            "127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 \
+             \"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\""
+        );
+        assert_eq!(
+            formatted(&AccessRecord {
+                user: None,
+                time: UNIX_EPOCH + Duration::from_secs(951_782_400),
+                path: "/a\"b\\c\n\u{202e}",
+                bytes: None,
+                referer: None,
+                user_agent: None,
+                latency: Some(Duration::from_micros(1234)),
+                ..record.clone()
+            }),
+            "127.0.0.1 - - [29/Feb/2000:00:00:00 +0000] \"GET /a\\\"b\\\\c\\x0a\\xe2\\x80\\xae HTTP/1.0\" 200 - \"-\" \"-\" 1234"
+        );
+
+        let dir = test_dir("access_log");
+        let options = LogOptions {
+            file_path: dir.join("access.log"),
+            ..LogOptions::default()
+        };
+        access_log::write_access_log(&options, LogType::FileSystem, &record).unwrap();
+        assert_eq!(
+            fs::read_to_string(&options.file_path).unwrap(),
+            format!("{}\n", formatted(&record))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_error_display() {
+        let error = io_error(Path::new("logs/log.txt"))(std::io::Error::other("disk full"));
+        assert_eq!(error.to_string(), "I/O error on logs/log.txt: disk full");
+        assert_eq!(
+            std::error::Error::source(&error).map(ToString::to_string),
+            Some("disk full".to_string())
+        );
+
+        let error = LogError::Io {
+            path: None,
+            source: std::io::Error::other("stream closed"),
+        };
+        assert_eq!(error.to_string(), "I/O error: stream closed");
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        let io = |kind: ErrorKind| LogError::Io {
+            path: None,
+            source: kind.into(),
+        };
+
+        assert_eq!(
+            io(ErrorKind::Interrupted).retry_after(),
+            Some(Duration::ZERO)
+        );
+        assert!(io(ErrorKind::TimedOut).is_transient());
+        assert!(io(ErrorKind::StorageFull).is_transient());
+        assert!(!io(ErrorKind::PermissionDenied).is_transient());
+        assert!(!LogError::Format("broken".to_string()).is_transient());
+        assert!(!LogError::Config("max_file_size".to_string()).is_transient());
+        assert!(!LogError::Unsupported(LogType::Network).is_transient());
+    }
+
+    #[test]
+    fn test_validate_options() {
+        let rotated = |max_bytes, keep| FileSizeCap {
+            max_bytes,
+            on_full: OnFull::Rotate { keep },
+        };
+        let error = |options: LogOptions| match options.validate() {
+            Err(LogError::Config(message)) => message,
+            result => panic!("Unexpected result {:?}", result),
+        };
+
+        assert!(LogOptions::default().validate().is_ok());
+        assert!(LogOptions {
+            max_file_size: Some(rotated(100, 2)),
+            max_total_size: Some(300),
+            dir_mode: Some(0o700),
+            ..LogOptions::default()
+        }
+        .validate()
+        .is_ok());
+
+        assert_eq!(
+            error(LogOptions {
+                max_file_size: Some(rotated(100, 2)),
+                max_total_size: Some(40),
+                ..LogOptions::default()
+            }),
+            "max_total_size (40) is smaller than max_file_size.max_bytes (100)"
+        );
+        assert!(error(LogOptions {
+            max_total_size: Some(40),
+            ..LogOptions::default()
+        })
+        .starts_with("max_total_size requires"));
+        assert_eq!(
+            error(LogOptions {
+                dir_mode: Some(0o600),
+                ..LogOptions::default()
+            }),
+            "dir_mode (0o600) lacks the owner's execute bit, the log file couldn't be created"
+        );
+        assert_eq!(
+            error(LogOptions {
+                file_mode: Some(0o10000),
+                ..LogOptions::default()
+            }),
+            "file_mode (0o10000) is not a file mode"
+        );
+        assert!(error(LogOptions {
+            file_path: PathBuf::from(".."),
+            ..LogOptions::default()
+        })
+        .starts_with("file_path"));
+        assert!(error(LogOptions {
+            signing_key: Some(signature::SigningKey::from_bytes(&[1; 32])),
+            ..LogOptions::default()
+        })
+        .starts_with("signing_key"));
+    }
+
+    #[test]
+    fn test_error_chain_fields() {
+        let error = std::io::Error::other("disk full");
+
+        assert_eq!(
+            error_chain_fields(&error, &Backtrace::disabled()),
+            r#"error="disk full""#
+        );
+        let fields = error_chain_fields(&error, &Backtrace::force_capture());
+        assert!(fields.contains(" backtrace=\""));
+        // The whole backtrace stays a single quoted field on one line
+        assert!(!fields.contains('\n'));
+    }
+
+    #[test]
+    fn test_scoped_prefix() {
+        let options = LogOptions::default();
+
+        assert_eq!(format_info(&options, "msg"), "[INFO] msg");
+        {
+            let _worker = scoped_prefix("worker-3");
+            assert_eq!(format_info(&options, "msg"), "[INFO] [worker-3] msg");
+            {
+                let _job = scoped_prefix(String::from("job-7"));
+                assert_eq!(
+                    format_info(&options, "msg"),
+                    "[INFO] [worker-3] [job-7] msg"
+                );
+
+                // Other threads are not affected
+                std::thread::spawn(|| {
+                    assert_eq!(format_info(&LogOptions::default(), "msg"), "[INFO] msg");
+                })
+                .join()
+                .unwrap();
+            }
+            assert_eq!(format_info(&options, "msg"), "[INFO] [worker-3] msg");
+        }
+        assert_eq!(format_info(&options, "msg"), "[INFO] msg");
+    }
+
+    #[test]
+    fn test_write_to_log_to_filesystem() {
+        let test_message = "Test log message";
+        let log_level = LogLevel::Info;
+
+        // 0. Remove the log file before the test, it might not exist yet
+        let _ = fs::remove_file(DEFAULT_LOG_FILE_NAME);
+
+        // 1. Create a message
+        let expected_output = format!("[{}] {}", log_level, test_message);
+
+        // 2. Write to log using write_to_log function
+        write_to_log(LogType::FileSystem, log_level, test_message).expect("Failed to write to log");
+
+        let file = fs::File::open(DEFAULT_LOG_FILE_NAME).expect("Failed to open log file");
+        let reader = BufReader::new(file);
+
+        // Here, we assume that the last line is tho only line in the fiile
+        let last_line = reader
+            .lines()
+            .last()
+            .expect("Log file is empty")
+            .expect("Failed to read from log file");
+
+        // 3. Validate the content of the log file by comparing it with the message
+        assert_eq!(last_line, expected_output);
+
+        // Cleanup: remove the log file after the test
+        fs::remove_file(DEFAULT_LOG_FILE_NAME).expect("Failed to delete test log file");
+    }
+}
+
+#[cfg(all(test, not(debug_assertions)))]
+mod release_tests {
+    use super::*;
+
+    #[test]
+    fn test_release_max_level() {
+        let bytes = |log_level| {
+            write_to_log(LogType::Console, log_level, "Test log message")
+                .unwrap()
+                .bytes
+        };
+
+        assert_eq!(
+            bytes(LogLevel::Error) == 0,
+            cfg!(feature = "release_max_level_off")
+        );
+        // The most restrictive feature wins
+        assert_eq!(
+            bytes(LogLevel::Debug) == 0,
+            cfg!(any(
+                feature = "release_max_level_off",
+                feature = "release_max_level_error",
+                feature = "release_max_level_warn",
+                feature = "release_max_level_info"
+            ))
+        );
     }
 }
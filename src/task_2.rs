@@ -1,3 +1,7 @@
+use std::collections::HashSet;
+use std::io::{self, BufRead};
+use std::iter::{Enumerate, FilterMap};
+
 // Initial versions:
 //
 // fn bar(value: &str) -> Option<&str> {
@@ -35,7 +39,7 @@
 // V1:
 // The bar function contract is unchanged
 // The foo function filters the values using the bar's return value + is_some
-mod v1 {
+pub mod v1 {
     fn bar(value: &str) -> Option<&str> {
         Some(value)
     }
@@ -58,7 +62,7 @@ mod v1 {
 // would not match the return type of foo when calling the collect function:
 //     `(usize, &str)` vs `(usize, T)`.
 // The foo funciion filters the values using filter_map in a single line
-mod v2 {
+pub mod v2 {
     fn bar<T: AsRef<str>>(value: T) -> Option<T> {
         Some(value)
     }
@@ -75,6 +79,750 @@ mod v2 {
     }
 }
 
+// V3:
+// The foo function accepts anything that implements `IntoIterator<Item = T>`,
+// so slices (`input.iter()`), iterator chains and map values (`map.into_values()`)
+// can be processed without collecting them into an intermediate Vec first.
+// foo_vec keeps the Vec-based signature of v1/v2 for existing callers.
+fn bar<T: AsRef<str>>(value: T) -> Option<T> {
+    Some(value)
+}
+
+/// Extension trait exposing the enumerate-filter pattern of [`foo`] on any iterator.
+///
+/// ```
+/// use nxlog_task::task_2::EnumerateFilterMap;
+///
+/// let output: Vec<_> = ["1", "x", "3"]
+///     .into_iter()
+///     .enumerate_filter_map(|value| value.parse::<i32>().ok())
+///     .collect();
+///
+/// assert_eq!(output, vec![(0, 1), (1, 3)]);
+/// ```
+pub trait EnumerateFilterMap: Iterator + Sized {
+    /// Keeps the items for which `f` returns `Some` and enumerates them after filtering.
+    fn enumerate_filter_map<U, F>(self, f: F) -> Enumerate<FilterMap<Self, F>>
+    where
+        F: FnMut(Self::Item) -> Option<U>,
+    {
+        self.filter_map(f).enumerate()
+    }
+}
+
+impl<I: Iterator> EnumerateFilterMap for I {}
+
+/// Values paired with their index.
+pub type Enumerated<T> = Vec<(usize, T)>;
+
+/// Filters `input` through `bar` and enumerates the accepted values.
+///
+/// The indices are positions in the output, i.e. they are contiguous and start from 0.
+pub fn foo<I, T>(input: I) -> Vec<(usize, T)>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    input.into_iter().enumerate_filter_map(bar).collect()
+}
+
+/// Vec-compatible wrapper around [`foo`].
+pub fn foo_vec<T>(input: Vec<T>) -> Vec<(usize, T)>
+where
+    T: AsRef<str>,
+{
+    foo(input)
+}
+
+// Strategies:
+// The bar function above is a stub that accepts everything. Strategy replaces it with
+// a caller-selected rule; foo_by filters with it, keeping the original values.
+
+/// A rule deciding which values [`foo_by`] keeps.
+pub trait Strategy {
+    /// Returns `true` if `value` should be kept.
+    fn accept(&self, value: &str) -> bool;
+}
+
+/// Any `Fn(&str) -> bool` is a strategy.
+impl<F: Fn(&str) -> bool> Strategy for F {
+    fn accept(&self, value: &str) -> bool {
+        self(value)
+    }
+}
+
+/// A strategy with a string parameter to normalize, so wrappers such as [`IgnoreCase`] compare
+/// the values with a parameter normalized the same way.
+pub trait MapParameter: Strategy + Sized {
+    /// Returns the strategy with `f` applied to its parameter. The default is for the strategies
+    /// without a parameter and returns the strategy as it is.
+    fn map_parameter(self, _f: &dyn Fn(&str) -> String) -> Self {
+        self
+    }
+}
+
+/// A closure has no parameter to normalize, it gets the normalized value only.
+impl<F: Fn(&str) -> bool> MapParameter for F {}
+
+/// Accepts everything, the same as `bar`.
+pub struct AcceptAll;
+
+impl MapParameter for AcceptAll {}
+
+impl Strategy for AcceptAll {
+    fn accept(&self, _value: &str) -> bool {
+        true
+    }
+}
+
+/// Rejects empty values.
+pub struct NonEmpty;
+
+impl MapParameter for NonEmpty {}
+
+impl Strategy for NonEmpty {
+    fn accept(&self, value: &str) -> bool {
+        !value.is_empty()
+    }
+}
+
+/// Rejects values that are empty after trimming whitespace.
+pub struct NonBlank;
+
+impl MapParameter for NonBlank {}
+
+impl Strategy for NonBlank {
+    fn accept(&self, value: &str) -> bool {
+        !value.trim().is_empty()
+    }
+}
+
+/// Accepts values starting with the prefix.
+pub struct Prefix(pub String);
+
+impl MapParameter for Prefix {
+    fn map_parameter(self, f: &dyn Fn(&str) -> String) -> Self {
+        Prefix(f(&self.0))
+    }
+}
+
+impl Strategy for Prefix {
+    fn accept(&self, value: &str) -> bool {
+        value.starts_with(self.0.as_str())
+    }
+}
+
+/// Accepts values equal to the string.
+pub struct Equals(pub String);
+
+impl MapParameter for Equals {
+    fn map_parameter(self, f: &dyn Fn(&str) -> String) -> Self {
+        Equals(f(&self.0))
+    }
+}
+
+impl Strategy for Equals {
+    fn accept(&self, value: &str) -> bool {
+        value == self.0
+    }
+}
+
+/// Applies the strategy to the case-folded value, making e.g. [`Prefix`] and [`Equals`]
+/// case-insensitive: `IgnoreCase::new(Prefix("Err".into()))` accepts `ERROR` and `error`.
+///
+/// The parameter of the strategy is folded by [`IgnoreCase::new`] the same way as the values,
+/// see [`pipeline::fold_case`]. Folding isn't locale-aware and there is no locale-aware
+/// collation, e.g. the Turkish `ı` and `I` are treated as `i` like in English.
+pub struct IgnoreCase<S>(S);
+
+impl<S: MapParameter> IgnoreCase<S> {
+    /// Wraps `strategy`, folding the case of its parameter.
+    pub fn new(strategy: S) -> Self {
+        IgnoreCase(strategy.map_parameter(&|parameter| pipeline::fold_case(parameter).into_owned()))
+    }
+}
+
+impl<S: Strategy> IgnoreCase<S> {
+    /// Wraps a strategy without a parameter to fold, e.g. one that doesn't implement
+    /// [`MapParameter`]. The strategy sees the folded values only.
+    pub fn wrap(strategy: S) -> Self {
+        IgnoreCase(strategy)
+    }
+}
+
+impl<S: MapParameter> MapParameter for IgnoreCase<S> {
+    fn map_parameter(self, f: &dyn Fn(&str) -> String) -> Self {
+        IgnoreCase::new(self.0.map_parameter(f))
+    }
+}
+
+impl<S: Strategy> Strategy for IgnoreCase<S> {
+    fn accept(&self, value: &str) -> bool {
+        self.0.accept(&pipeline::fold_case(value))
+    }
+}
+
+/// Accepts values matching the regular expression anywhere, use `^...$` to match the whole value.
+///
+/// The pattern isn't a plain string, so wrappers leave it as it is: write it for the normalized
+/// values, e.g. lowercase under [`IgnoreCase`], or use `(?i)`.
+pub struct RegexMatch(pub regex::Regex);
+
+impl MapParameter for RegexMatch {}
+
+impl Strategy for RegexMatch {
+    fn accept(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+/// Accepts values with at most the given number of chars.
+pub struct MaxLength(pub usize);
+
+impl MapParameter for MaxLength {}
+
+impl Strategy for MaxLength {
+    fn accept(&self, value: &str) -> bool {
+        value.chars().count() <= self.0
+    }
+}
+
+/// Accepts values with at most the given number of grapheme clusters, i.e. of user-perceived
+/// characters: `e\u{301}` (e + combining acute) and `👍🏽` are one each.
+#[cfg(feature = "unicode")]
+pub struct MaxGraphemes(pub usize);
+
+#[cfg(feature = "unicode")]
+impl MapParameter for MaxGraphemes {}
+
+#[cfg(feature = "unicode")]
+impl Strategy for MaxGraphemes {
+    fn accept(&self, value: &str) -> bool {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        value.graphemes(true).nth(self.0).is_none()
+    }
+}
+
+/// Applies the strategy to the NFC normalization of the value, so the precomposed and the
+/// decomposed forms of the same text (`é` and `e\u{301}`) are treated the same.
+///
+/// The parameter of the strategy (e.g. the [`Prefix`]) is normalized by [`Nfc::new`] too.
+#[cfg(feature = "unicode")]
+pub struct Nfc<S>(S);
+
+#[cfg(feature = "unicode")]
+impl<S: MapParameter> Nfc<S> {
+    /// Wraps `strategy`, normalizing its parameter to NFC.
+    pub fn new(strategy: S) -> Self {
+        Nfc(strategy.map_parameter(&|parameter| pipeline::nfc(parameter).into_owned()))
+    }
+}
+
+#[cfg(feature = "unicode")]
+impl<S: Strategy> Nfc<S> {
+    /// Wraps a strategy without a parameter to normalize, e.g. one that doesn't implement
+    /// [`MapParameter`]. The strategy sees the normalized values only.
+    pub fn wrap(strategy: S) -> Self {
+        Nfc(strategy)
+    }
+}
+
+#[cfg(feature = "unicode")]
+impl<S: MapParameter> MapParameter for Nfc<S> {
+    fn map_parameter(self, f: &dyn Fn(&str) -> String) -> Self {
+        Nfc::new(self.0.map_parameter(f))
+    }
+}
+
+#[cfg(feature = "unicode")]
+impl<S: Strategy> Strategy for Nfc<S> {
+    fn accept(&self, value: &str) -> bool {
+        self.0.accept(&pipeline::nfc(value))
+    }
+}
+
+/// Like [`foo`], but keeps only the values accepted by `strategy`.
+///
+/// # Arguments
+///
+/// * `input` - values to filter.
+///
+/// * `strategy` - one of the built-in strategies, or a closure `Fn(&str) -> bool`.
+pub fn foo_by<I, T, S>(input: I, strategy: &S) -> Vec<(usize, T)>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+    S: Strategy + ?Sized,
+{
+    input
+        .into_iter()
+        .enumerate_filter_map(|value| strategy.accept(value.as_ref()).then_some(value))
+        .collect()
+}
+
+/// Like [`foo`], but drops duplicate values, the first occurrence wins.
+///
+/// The duplicates are removed before enumerating, so the indices stay contiguous.
+pub fn foo_dedup<I, T>(input: I) -> Vec<(usize, T)>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    let mut seen = HashSet::new();
+
+    input
+        .into_iter()
+        .filter(|value| seen.insert(value.as_ref().to_owned()))
+        .enumerate_filter_map(bar)
+        .collect()
+}
+
+/// Zero-copy version of [`foo`] for borrowed slices, e.g. `&[&str]` or `&[String]`.
+///
+/// The output borrows from `input`, so owned Strings are neither moved nor cloned.
+pub fn foo_slice<T>(input: &[T]) -> Vec<(usize, &str)>
+where
+    T: AsRef<str>,
+{
+    input
+        .iter()
+        .map(AsRef::as_ref)
+        .enumerate_filter_map(bar)
+        .collect()
+}
+
+/// Like [`foo`], but returns the rejected values too, instead of silently discarding them.
+///
+/// The accepted values are enumerated as in [`foo`], the rejected ones carry their input
+/// position (the same convention as [`try_foo_partial`]) so they can be reported or retried.
+pub fn partition_foo<I, T>(input: I) -> (Enumerated<T>, Enumerated<T>)
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    partition_foo_by(input, &AcceptAll)
+}
+
+/// Like [`partition_foo`], but splits the values with `strategy`, see [`foo_by`].
+pub fn partition_foo_by<I, T, S>(input: I, strategy: &S) -> (Enumerated<T>, Enumerated<T>)
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+    S: Strategy + ?Sized,
+{
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+
+    for (position, value) in input.into_iter().enumerate() {
+        if strategy.accept(value.as_ref()) {
+            accepted.push((accepted.len(), value));
+        } else {
+            rejected.push((position, value));
+        }
+    }
+
+    (accepted, rejected)
+}
+
+/// Like [`foo`], but writes into `output`, reusing its allocation.
+///
+/// `output` is cleared first. Together with a borrowed input (`input.iter()`) this is the
+/// zero-clone path: neither the values nor the output buffer are reallocated between calls.
+pub fn foo_into<I, T>(input: I, output: &mut Vec<(usize, T)>)
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    output.clear();
+    output.extend(input.into_iter().enumerate_filter_map(bar));
+}
+
+/// Like [`foo`], but enumerates the input before filtering.
+///
+/// The indices are the original positions of the accepted values in the input,
+/// so they are increasing but not necessarily contiguous.
+pub fn foo_original_indices<I, T>(input: I) -> Vec<(usize, T)>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    input
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, value)| bar(value).map(|value| (index, value)))
+        .collect()
+}
+
+/// Like [`foo_with`], but enumerates the input before filtering, see [`foo_original_indices`].
+pub fn foo_with_original_indices<I, T, U, F>(input: I, f: F) -> Vec<(usize, U)>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+    F: Fn(&str) -> Option<U>,
+{
+    input
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, value)| f(value.as_ref()).map(|value| (index, value)))
+        .collect()
+}
+
+/// Like [`foo`], but replaces the hard-coded `bar` with a caller-supplied function.
+///
+/// `f` receives each value as `&str` and returns `Some` with the (possibly transformed)
+/// value to keep it, or `None` to drop it. Accepted values are enumerated after filtering.
+///
+/// # Arguments
+///
+/// * `input` - values to filter.
+///
+/// * `f` - a parsing/validation function applied to every value.
+pub fn foo_with<I, T, U, F>(input: I, f: F) -> Vec<(usize, U)>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+    F: Fn(&str) -> Option<U>,
+{
+    input
+        .into_iter()
+        .enumerate_filter_map(|value| f(value.as_ref()))
+        .collect()
+}
+
+/// Fallible version of [`foo_with`] for validators that can fail, not just reject.
+///
+/// `f` returns `Ok(Some(_))` to accept a value, `Ok(None)` to reject it and `Err(_)` on failure.
+/// Returns the first error, otherwise the accepted values enumerated as in [`foo`].
+pub fn try_foo<I, T, U, E, F>(input: I, f: F) -> Result<Vec<(usize, U)>, E>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+    F: Fn(&str) -> Result<Option<U>, E>,
+{
+    input
+        .into_iter()
+        .enumerate_filter_map(|value| f(value.as_ref()).transpose())
+        .map(|(index, value)| value.map(|value| (index, value)))
+        .collect()
+}
+
+/// Like [`try_foo`], but doesn't stop on the first error.
+///
+/// Returns the accepted values enumerated as in [`foo`], and the errors together with
+/// the input position of the value that caused them.
+pub fn try_foo_partial<I, T, U, E, F>(input: I, f: F) -> (Enumerated<U>, Enumerated<E>)
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+    F: Fn(&str) -> Result<Option<U>, E>,
+{
+    let mut accepted = Vec::new();
+    let mut errors = Vec::new();
+
+    for (position, value) in input.into_iter().enumerate() {
+        match f(value.as_ref()) {
+            Ok(Some(value)) => accepted.push((accepted.len(), value)),
+            Ok(None) => {}
+            Err(e) => errors.push((position, e)),
+        }
+    }
+
+    (accepted, errors)
+}
+
+/// Streaming version of [`foo`] over the lines of a file, stdin or any other reader.
+///
+/// The lines are read lazily, one at a time, and each accepted line is yielded together with
+/// its 1-based line number in the input. I/O errors are yielded as `Err` and don't stop the
+/// iteration, so the caller decides whether to skip them or bail out.
+pub fn foo_lines<R: BufRead>(reader: R) -> impl Iterator<Item = io::Result<(usize, String)>> {
+    // The lines are owned already, no need to copy them like foo_lines_with does
+    reader
+        .lines()
+        .enumerate()
+        .map(|(index, line)| line.map(|line| (index + 1, line)))
+}
+
+/// Like [`foo_lines`], but filters/transforms each line with `f`, see [`foo_with`].
+pub fn foo_lines_with<R, U, F>(reader: R, f: F) -> impl Iterator<Item = io::Result<(usize, U)>>
+where
+    R: BufRead,
+    F: Fn(&str) -> Option<U>,
+{
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(move |(index, line)| match line {
+            Ok(line) => f(&line).map(|value| Ok((index + 1, value))),
+            Err(e) => Some(Err(e)),
+        })
+}
+
+/// Async version of [`foo`] for pipelines consuming a `Stream`, e.g. network data.
+///
+/// Returns a stream yielding the accepted values enumerated after filtering.
+#[cfg(feature = "tokio")]
+pub fn foo_stream<S, T>(input: S) -> impl tokio_stream::Stream<Item = (usize, T)>
+where
+    S: tokio_stream::Stream<Item = T>,
+    T: AsRef<str>,
+{
+    use tokio_stream::StreamExt;
+
+    enumerate_stream(input.filter_map(bar))
+}
+
+/// Like [`foo_stream`], but filters/transforms each value with `f`, see [`foo_with`].
+#[cfg(feature = "tokio")]
+pub fn foo_stream_with<S, T, U, F>(input: S, f: F) -> impl tokio_stream::Stream<Item = (usize, U)>
+where
+    S: tokio_stream::Stream<Item = T>,
+    T: AsRef<str>,
+    F: Fn(&str) -> Option<U>,
+{
+    use tokio_stream::StreamExt;
+
+    enumerate_stream(input.filter_map(move |value| f(value.as_ref())))
+}
+
+// tokio_stream has no enumerate(), so count the values manually
+#[cfg(feature = "tokio")]
+fn enumerate_stream<S: tokio_stream::Stream>(
+    input: S,
+) -> impl tokio_stream::Stream<Item = (usize, S::Item)> {
+    use tokio_stream::StreamExt;
+
+    let mut index = 0;
+    input.map(move |value| {
+        let output = (index, value);
+        index += 1;
+        output
+    })
+}
+
+/// Parallel version of [`foo`] for large inputs.
+///
+/// The values are filtered in parallel, but the output order (and so the indices)
+/// matches the sequential [`foo`].
+#[cfg(feature = "rayon")]
+pub fn par_foo<I, T>(input: I) -> Vec<(usize, T)>
+where
+    I: rayon::iter::IntoParallelIterator<Item = T>,
+    T: AsRef<str> + Send,
+{
+    use rayon::prelude::*;
+
+    // collect() keeps the original order, so enumerating afterwards is deterministic
+    input
+        .into_par_iter()
+        .filter_map(bar)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .enumerate()
+        .collect()
+}
+
+/// Parallel version of [`foo_with`], `f` is called from multiple threads.
+#[cfg(feature = "rayon")]
+pub fn par_foo_with<I, T, U, F>(input: I, f: F) -> Vec<(usize, U)>
+where
+    I: rayon::iter::IntoParallelIterator<Item = T>,
+    T: AsRef<str> + Send,
+    U: Send,
+    F: Fn(&str) -> Option<U> + Sync + Send,
+{
+    use rayon::prelude::*;
+
+    input
+        .into_par_iter()
+        .filter_map(|value| f(value.as_ref()))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .enumerate()
+        .collect()
+}
+
+// Pipeline:
+// The foo variants above each hard-code one combination of filtering, transforming and
+// enumerating. Pipeline chains the stages instead, every stage being a plain function
+// (or Strategy) that can be tested on its own.
+pub mod pipeline {
+    use super::{Enumerated, Strategy};
+    use std::borrow::Cow;
+
+    /// Rejects empty values, a filter stage.
+    pub fn non_empty(value: &str) -> bool {
+        !value.is_empty()
+    }
+
+    /// Rejects values that are empty after trimming whitespace, a filter stage.
+    pub fn non_blank(value: &str) -> bool {
+        !value.trim().is_empty()
+    }
+
+    /// Removes the leading and trailing whitespace, a map stage.
+    pub fn trim(value: &str) -> Cow<'_, str> {
+        Cow::Borrowed(value.trim())
+    }
+
+    /// Lowercases the value, a map stage. Borrows if it's lowercase already.
+    pub fn lowercase(value: &str) -> Cow<'_, str> {
+        if value.chars().any(char::is_uppercase) {
+            Cow::Owned(value.to_lowercase())
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+
+    /// Folds the case of the value for caseless comparisons, a map stage: `Straße`, `STRASSE`
+    /// and `strasse` all become `strasse`. Borrows if it's folded already.
+    ///
+    /// Every char is lowercased, uppercased and lowercased again, which matches the Unicode
+    /// full case folding for all but a few chars, such as the Turkish dotless `ı`.
+    pub fn fold_case(value: &str) -> Cow<'_, str> {
+        if value.is_ascii() {
+            return lowercase(value);
+        }
+
+        let folded: String = value
+            .chars()
+            .flat_map(char::to_lowercase)
+            .flat_map(char::to_uppercase)
+            .flat_map(char::to_lowercase)
+            .collect();
+        if folded == value {
+            Cow::Borrowed(value)
+        } else {
+            Cow::Owned(folded)
+        }
+    }
+
+    /// Normalizes the value to NFC, a map stage. Borrows if it's NFC already.
+    #[cfg(feature = "unicode")]
+    pub fn nfc(value: &str) -> Cow<'_, str> {
+        use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+        match is_nfc_quick(value.chars()) {
+            IsNormalized::Yes => Cow::Borrowed(value),
+            _ => {
+                let normalized: String = value.nfc().collect();
+                if normalized == value {
+                    Cow::Borrowed(value)
+                } else {
+                    Cow::Owned(normalized)
+                }
+            }
+        }
+    }
+
+    type MapStage<'a> = Box<dyn for<'v> Fn(&'v str) -> Cow<'v, str> + 'a>;
+
+    enum Stage<'a> {
+        Filter(Box<dyn Strategy + 'a>),
+        Map(MapStage<'a>),
+    }
+
+    /// Filter and map stages applied in order to every value, e.g.
+    ///
+    /// ```
+    /// use nxlog_task::task_2::pipeline::{non_empty, trim, Pipeline};
+    ///
+    /// let output = Pipeline::new()
+    ///     .map(trim)
+    ///     .filter(non_empty)
+    ///     .enumerate()
+    ///     .collect([" a ", "  ", "b"]);
+    ///
+    /// assert_eq!(output, vec![(0, "a".to_string()), (1, "b".to_string())]);
+    /// ```
+    #[derive(Default)]
+    pub struct Pipeline<'a> {
+        stages: Vec<Stage<'a>>,
+    }
+
+    impl<'a> Pipeline<'a> {
+        /// Creates a pipeline without stages, keeping every value as it is.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Adds a stage keeping only the values accepted by `strategy`, see [`super::foo_by`].
+        pub fn filter(mut self, strategy: impl Strategy + 'a) -> Self {
+            self.stages.push(Stage::Filter(Box::new(strategy)));
+            self
+        }
+
+        /// Adds a stage replacing every value with `f(value)`.
+        pub fn map<F>(mut self, f: F) -> Self
+        where
+            F: for<'v> Fn(&'v str) -> Cow<'v, str> + 'a,
+        {
+            self.stages.push(Stage::Map(Box::new(f)));
+            self
+        }
+
+        /// Pairs the output values with their index, counted after all the stages as in
+        /// [`super::foo`].
+        pub fn enumerate(self) -> EnumeratedPipeline<'a> {
+            EnumeratedPipeline { pipeline: self }
+        }
+
+        /// Runs the stages on a single value, `None` if a filter rejected it.
+        pub fn apply<'v>(&self, value: &'v str) -> Option<Cow<'v, str>> {
+            let mut value = Cow::Borrowed(value);
+            for stage in &self.stages {
+                match stage {
+                    Stage::Filter(strategy) => {
+                        if !strategy.accept(&value) {
+                            return None;
+                        }
+                    }
+                    Stage::Map(f) => {
+                        value = match value {
+                            Cow::Borrowed(value) => f(value),
+                            Cow::Owned(value) => Cow::Owned(f(&value).into_owned()),
+                        };
+                    }
+                }
+            }
+            Some(value)
+        }
+
+        /// Runs the stages on every value of `input`, returns the accepted values.
+        pub fn collect<I>(&self, input: I) -> Vec<String>
+        where
+            I: IntoIterator,
+            I::Item: AsRef<str>,
+        {
+            input
+                .into_iter()
+                .filter_map(|value| self.apply(value.as_ref()).map(Cow::into_owned))
+                .collect()
+        }
+    }
+
+    /// A [`Pipeline`] whose output values are enumerated, see [`Pipeline::enumerate`].
+    pub struct EnumeratedPipeline<'a> {
+        pipeline: Pipeline<'a>,
+    }
+
+    impl EnumeratedPipeline<'_> {
+        /// Runs the stages on every value of `input`, returns the accepted values enumerated.
+        pub fn collect<I>(&self, input: I) -> Enumerated<String>
+        where
+            I: IntoIterator,
+            I::Item: AsRef<str>,
+        {
+            self.pipeline
+                .collect(input)
+                .into_iter()
+                .enumerate()
+                .collect()
+        }
+    }
+}
+
 pub fn run() {
     let slice_input = vec!["string_0", "string_1", "string_2", ""];
     let string_input = vec![
@@ -96,13 +844,409 @@ pub fn run() {
     }
 
     // v2
-    let slice_output = v2::foo(slice_input);
+    let slice_output = v2::foo(slice_input.clone());
     for i in slice_output {
         println!("{:?}", i);
     }
 
-    let string_output = v2::foo(string_input);
+    let string_output = v2::foo(string_input.clone());
     for i in string_output {
         println!("{:?}", i);
     }
+
+    // v3
+    let slice_output = foo(slice_input.iter());
+    for i in slice_output {
+        println!("{:?}", i);
+    }
+
+    let string_output = foo_vec(string_input.clone());
+    for i in string_output {
+        println!("{:?}", i);
+    }
+
+    // foo_with: drop empty values and keep the length only
+    let len_output = foo_with(string_input, |value| {
+        (!value.is_empty()).then_some(value.len())
+    });
+    for i in len_output {
+        println!("{:?}", i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_foo_accepts_any_into_iterator() {
+        let expected = vec![(0, "a"), (1, "b"), (2, "")];
+
+        // Vec, slice iterator and map values give the same output
+        assert_eq!(foo(vec!["a", "b", ""]), expected);
+        assert_eq!(foo(["a", "b", ""].iter().copied()), expected);
+
+        let map = BTreeMap::from([(1, "a"), (2, "b"), (3, "")]);
+        assert_eq!(foo(map.into_values()), expected);
+    }
+
+    #[test]
+    fn test_foo_vec_matches_v1_and_v2() {
+        let input = vec![String::from("a"), String::from(""), String::from("c")];
+
+        assert_eq!(foo_vec(input.clone()), v1::foo(input.clone()));
+        assert_eq!(foo_vec(input.clone()), v2::foo(input));
+    }
+
+    #[test]
+    fn test_foo_dedup_keeps_first_occurrence() {
+        let input = vec!["b", "a", "b", "", "a", "c", ""];
+
+        assert_eq!(
+            foo_dedup(input),
+            vec![(0, "b"), (1, "a"), (2, ""), (3, "c")]
+        );
+    }
+
+    #[test]
+    fn test_foo_slice_borrows_input() {
+        let owned = vec![String::from("a"), String::from("")];
+        let slices = ["a", ""];
+
+        let output = foo_slice(&owned);
+
+        assert_eq!(output, vec![(0, "a"), (1, "")]);
+        assert_eq!(output, foo_slice(&slices));
+        assert!(std::ptr::eq(output[0].1, owned[0].as_str()));
+    }
+
+    #[test]
+    fn test_pipeline_stages() {
+        use pipeline::{lowercase, non_blank, non_empty, trim, Pipeline};
+        use std::borrow::Cow;
+
+        assert!(!non_empty("") && non_empty(" "));
+        assert!(!non_blank(" \t") && non_blank(" a "));
+        assert_eq!(trim(" a "), "a");
+        assert!(matches!(lowercase("abc"), Cow::Borrowed("abc")));
+        assert_eq!(lowercase("AbC"), "abc");
+
+        // Filters see the output of the previous map stages
+        let pipeline = Pipeline::new()
+            .filter(non_empty)
+            .map(trim)
+            .filter(MaxLength(3))
+            .map(lowercase);
+        assert_eq!(pipeline.apply(" ABC "), Some(Cow::Owned("abc".to_string())));
+        assert_eq!(pipeline.apply("abcd"), None);
+        assert_eq!(pipeline.collect(["", " A ", "toolong", "b"]), ["a", "b"]);
+        assert_eq!(
+            pipeline
+                .enumerate()
+                .collect(vec![String::from("X"), String::new(), String::from("Y")]),
+            vec![(0, "x".to_string()), (1, "y".to_string())]
+        );
+        assert_eq!(Pipeline::new().collect(["", "a"]), ["", "a"]);
+
+        let closures = Pipeline::new()
+            .filter(|value: &str| value.contains('-'))
+            .map(|value| Cow::Owned(value.replace('-', "_")));
+        assert_eq!(closures.collect(["a-b", "c"]), ["a_b"]);
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_unicode_strategies() {
+        use std::borrow::Cow;
+
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+
+        assert!(matches!(pipeline::nfc(composed), Cow::Borrowed(_)));
+        assert_eq!(pipeline::nfc(decomposed), composed);
+
+        let prefix = Nfc::new(Prefix(composed.to_string()));
+        assert_eq!(
+            foo_by([composed, decomposed, "cafe"], &prefix),
+            vec![(0, composed), (1, decomposed)]
+        );
+
+        // The parameter is normalized too
+        let prefix = Nfc::new(Prefix(decomposed.to_string()));
+        assert_eq!(
+            foo_by([composed, decomposed, "cafe"], &prefix),
+            vec![(0, composed), (1, decomposed)]
+        );
+        let equals = IgnoreCase::new(Nfc::new(Equals("CAFE\u{301}".to_string())));
+        assert_eq!(
+            foo_by(["Caf\u{e9}", "CAF\u{c9}", "cafe"], &equals),
+            vec![(0, "Caf\u{e9}"), (1, "CAF\u{c9}")]
+        );
+
+        // 5 chars, but 4 user-perceived characters
+        assert!(!MaxLength(4).accept(decomposed));
+        assert!(MaxGraphemes(4).accept(decomposed));
+        assert!(!MaxGraphemes(3).accept(decomposed));
+        assert!(MaxGraphemes(1).accept("\u{1f44d}\u{1f3fd}"));
+    }
+
+    #[test]
+    fn test_case_insensitive_strategies() {
+        let input = ["ERROR: disk", "Error", "warning", "error", "ÉCHEC"];
+
+        assert_eq!(
+            foo_by(input, &Equals("error".to_string())),
+            vec![(0, "error")]
+        );
+        assert_eq!(
+            foo_by(input, &IgnoreCase::new(Equals("error".to_string()))),
+            vec![(0, "Error"), (1, "error")]
+        );
+        assert_eq!(
+            foo_by(input, &IgnoreCase::new(Prefix("err".to_string()))),
+            vec![(0, "ERROR: disk"), (1, "Error"), (2, "error")]
+        );
+        assert_eq!(
+            foo_by(input, &IgnoreCase::new(Prefix("éch".to_string()))),
+            vec![(0, "ÉCHEC")]
+        );
+
+        // The parameter is folded too
+        assert_eq!(
+            foo_by(input, &IgnoreCase::new(Prefix("ERR".to_string()))),
+            vec![(0, "ERROR: disk"), (1, "Error"), (2, "error")]
+        );
+        assert_eq!(
+            foo_by(input, &IgnoreCase::new(Equals("Warning".to_string()))),
+            vec![(0, "warning")]
+        );
+        assert_eq!(
+            foo_by(
+                ["STRASSE", "Straße", "strasse", "STRAẞE", "strand"],
+                &IgnoreCase::new(Equals("straße".to_string()))
+            ),
+            vec![(0, "STRASSE"), (1, "Straße"), (2, "strasse"), (3, "STRAẞE")]
+        );
+        assert_eq!(pipeline::fold_case("ΌΣΟΣ"), pipeline::fold_case("όσος"));
+
+        // Closures and regular expressions see the folded values
+        let starts_with_err = |value: &str| value.starts_with("err");
+        assert_eq!(
+            foo_by(input, &IgnoreCase::new(starts_with_err)),
+            vec![(0, "ERROR: disk"), (1, "Error"), (2, "error")]
+        );
+        let regex = RegexMatch(regex::Regex::new("^err").unwrap());
+        assert_eq!(
+            foo_by(input, &IgnoreCase::new(regex)),
+            vec![(0, "ERROR: disk"), (1, "Error"), (2, "error")]
+        );
+
+        // A strategy that doesn't implement MapParameter
+        struct Short;
+        impl Strategy for Short {
+            fn accept(&self, value: &str) -> bool {
+                value.len() <= 5
+            }
+        }
+        assert_eq!(
+            foo_by(input, &IgnoreCase::wrap(Short)),
+            vec![(0, "Error"), (1, "error")]
+        );
+        assert!(matches!(
+            pipeline::fold_case("strasse"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+
+        // Per pipeline, every following stage sees the lowercase value
+        let pipeline = pipeline::Pipeline::new()
+            .map(pipeline::lowercase)
+            .filter(Prefix("err".to_string()));
+        assert_eq!(pipeline.collect(input), ["error: disk", "error", "error"]);
+    }
+
+    #[test]
+    fn test_foo_into_reuses_output() {
+        let input = [String::from("a"), String::from("b")];
+        let mut output = Vec::with_capacity(4);
+        output.push((7, &input[0]));
+        let (capacity, ptr) = (output.capacity(), output.as_ptr());
+
+        foo_into(input.iter(), &mut output);
+        assert_eq!(output, foo(input.iter()));
+        foo_into(input.iter(), &mut output);
+        assert_eq!(output, foo(input.iter()));
+
+        // The same allocation, it was large enough
+        assert_eq!(output.capacity(), capacity);
+        assert_eq!(output.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_foo_by_built_in_strategies() {
+        let input = vec!["apple", "", "  ", "apricot", "banana"];
+
+        assert_eq!(foo_by(input.clone(), &AcceptAll), foo(input.clone()));
+        assert_eq!(
+            foo_by(input.clone(), &NonEmpty),
+            vec![(0, "apple"), (1, "  "), (2, "apricot"), (3, "banana")]
+        );
+        assert_eq!(
+            foo_by(input.clone(), &NonBlank),
+            vec![(0, "apple"), (1, "apricot"), (2, "banana")]
+        );
+        assert_eq!(
+            foo_by(input.clone(), &Prefix(String::from("ap"))),
+            vec![(0, "apple"), (1, "apricot")]
+        );
+        assert_eq!(
+            foo_by(
+                input.clone(),
+                &RegexMatch(regex::Regex::new("^b.*a$").unwrap())
+            ),
+            vec![(0, "banana")]
+        );
+        assert_eq!(foo_by(input, &MaxLength(2)), vec![(0, ""), (1, "  ")]);
+    }
+
+    #[test]
+    fn test_foo_by_closure_and_dyn_strategy() {
+        let strategies: Vec<Box<dyn Strategy>> = vec![Box::new(NonEmpty), Box::new(MaxLength(1))];
+        let input = vec!["a", "", "bc"];
+
+        assert_eq!(foo_by(input.clone(), strategies[0].as_ref()).len(), 2);
+        assert_eq!(foo_by(input.clone(), strategies[1].as_ref()).len(), 2);
+        assert_eq!(foo_by(input, &|value: &str| value == "bc"), vec![(0, "bc")]);
+    }
+
+    #[test]
+    fn test_partition_foo_returns_rejected() {
+        let input = vec!["a", "", "b", " "];
+
+        let (accepted, rejected) = partition_foo(input.clone());
+        assert_eq!(accepted, foo(input.clone()));
+        assert!(rejected.is_empty());
+
+        let (accepted, rejected) = partition_foo_by(input, &NonBlank);
+        assert_eq!(accepted, vec![(0, "a"), (1, "b")]);
+        assert_eq!(rejected, vec![(1, ""), (3, " ")]);
+    }
+
+    #[test]
+    fn test_foo_original_indices() {
+        assert_eq!(
+            foo_original_indices(vec!["a", "b", ""]),
+            vec![(0, "a"), (1, "b"), (2, "")]
+        );
+
+        let output =
+            foo_with_original_indices(vec!["1", "x", "3", ""], |value| value.parse::<i32>().ok());
+        assert_eq!(output, vec![(0, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn test_foo_with_parses_and_filters() {
+        let input = vec!["1", "x", "3", ""];
+
+        let output = foo_with(input, |value| value.parse::<i32>().ok());
+
+        assert_eq!(output, vec![(0, 1), (1, 3)]);
+    }
+
+    // Rejects empty values, fails on non-numeric ones
+    fn parse_non_empty(value: &str) -> Result<Option<i32>, std::num::ParseIntError> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        value.parse().map(Some)
+    }
+
+    #[test]
+    fn test_try_foo_ok() {
+        let output = try_foo(vec!["1", "", "3"], parse_non_empty);
+
+        assert_eq!(output, Ok(vec![(0, 1), (1, 3)]));
+    }
+
+    #[test]
+    fn test_try_foo_returns_first_error() {
+        let output = try_foo(vec!["1", "x", "y"], parse_non_empty);
+
+        assert_eq!(output, Err("x".parse::<i32>().unwrap_err()));
+    }
+
+    #[test]
+    fn test_try_foo_partial_collects_errors() {
+        let (accepted, errors) = try_foo_partial(vec!["1", "x", "", "4"], parse_non_empty);
+
+        assert_eq!(accepted, vec![(0, 1), (1, 4)]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+    }
+
+    #[test]
+    fn test_foo_lines_yields_line_numbers() {
+        let reader = io::Cursor::new("first\n\nthird\n");
+
+        let output: Vec<_> = foo_lines(reader).collect::<io::Result<_>>().unwrap();
+        assert_eq!(
+            output,
+            vec![
+                (1, String::from("first")),
+                (2, String::new()),
+                (3, String::from("third"))
+            ]
+        );
+
+        let reader = io::Cursor::new("first\n\nthird\n");
+        let output: Vec<_> =
+            foo_lines_with(reader, |line| (!line.is_empty()).then_some(line.len()))
+                .collect::<io::Result<_>>()
+                .unwrap();
+        assert_eq!(output, vec![(1, 5), (3, 5)]);
+    }
+
+    #[test]
+    fn test_foo_lines_yields_io_errors() {
+        // Invalid UTF-8 on the second line
+        let reader = io::Cursor::new(b"ok\n\xff\nok\n".to_vec());
+
+        let output: Vec<_> = foo_lines(reader).collect();
+        assert_eq!(output.len(), 3);
+        assert!(output[1].is_err());
+        assert_eq!(output[2].as_ref().unwrap(), &(3, String::from("ok")));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_foo_stream_matches_foo() {
+        use tokio_stream::StreamExt;
+
+        let input = vec!["1", "x", "3", ""];
+
+        let output: Vec<_> = foo_stream(tokio_stream::iter(input.clone()))
+            .collect()
+            .await;
+        assert_eq!(output, foo(input.clone()));
+
+        let output: Vec<_> = foo_stream_with(tokio_stream::iter(input.clone()), |value| {
+            value.parse::<i32>().ok()
+        })
+        .collect()
+        .await;
+        assert_eq!(output, foo_with(input, |value| value.parse::<i32>().ok()));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_foo_matches_foo() {
+        let input: Vec<String> = (0..10_000).map(|i| (i % 3).to_string()).collect();
+        let non_zero = |value: &str| value.parse::<u32>().ok().filter(|n| *n != 0);
+
+        assert_eq!(par_foo(input.clone()), foo(input.clone()));
+        assert_eq!(
+            par_foo_with(input.clone(), non_zero),
+            foo_with(input, non_zero)
+        );
+    }
 }
@@ -0,0 +1,91 @@
+use nxlog_task::task_1::{self, LogLevel, LogOptions, MultiLine};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the allocations of the current thread, so other test threads don't interfere
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn formatting_into_a_reused_buffer_does_not_allocate() {
+    let messages = [
+        "short",
+        "a longer message with a number 42",
+        "multi\nline\nmessage",
+    ];
+
+    for multi_line in [
+        MultiLine::Raw,
+        MultiLine::Indent,
+        MultiLine::Escape,
+        MultiLine::Frame,
+    ] {
+        let options = LogOptions {
+            multi_line,
+            ..LogOptions::default()
+        };
+        let format_all = || {
+            for _ in 0..100 {
+                for message in messages {
+                    task_1::with_record_buffer(|buffer| {
+                        task_1::format_record_into(&options, buffer, LogLevel::Info, message);
+                    });
+                }
+            }
+        };
+
+        // The first records grow the buffer to its working size
+        format_all();
+        assert_eq!(allocations(format_all), 0, "{:?}", multi_line);
+    }
+}
+
+#[test]
+fn escaping_allocates_only_when_needed() {
+    let options = LogOptions::default();
+    let mut buffer = String::with_capacity(256);
+
+    assert_eq!(
+        allocations(|| {
+            buffer.clear();
+            task_1::format_record_into(&options, &mut buffer, LogLevel::Warn, "clean message");
+        }),
+        0
+    );
+    assert_ne!(
+        allocations(|| {
+            buffer.clear();
+            task_1::format_record_into(&options, &mut buffer, LogLevel::Warn, "\x1b[31mred");
+        }),
+        0
+    );
+    assert_eq!(buffer, "[WARN] \\u{1b}[31mred");
+}
@@ -0,0 +1,47 @@
+use nxlog_task::task_1::{memory_sink, LogLevel};
+use nxlog_task::{assert_logged, assert_not_logged};
+
+#[test]
+fn records_are_captured_per_call() {
+    let (answer, records) = memory_sink::capture(|| {
+        log::info!("first");
+        log::debug!("second");
+        42
+    });
+
+    assert_eq!(answer, 42);
+    let records: Vec<_> = records
+        .into_iter()
+        .map(|record| (record.level, record.message))
+        .collect();
+    assert_eq!(
+        records,
+        [
+            (LogLevel::Info, "first".to_string()),
+            (LogLevel::Debug, "second".to_string())
+        ]
+    );
+}
+
+#[test]
+fn nested_captures_keep_their_records() {
+    let (_, outer) = memory_sink::capture(|| {
+        log::warn!("outer");
+        let (_, inner) = memory_sink::capture(|| log::warn!("inner"));
+        assert_eq!(inner.len(), 1);
+    });
+    assert_eq!(outer.len(), 1);
+    assert_eq!(outer[0].message, "outer");
+}
+
+#[test]
+fn assert_logged_matches_level_and_pattern() {
+    assert_logged!(LogLevel::Error, r"^disk \w+$", || log::error!("disk full"));
+    assert_not_logged!(LogLevel::Warn, "disk", || log::error!("disk full"));
+}
+
+#[test]
+#[should_panic(expected = "expected a WARN record matching `disk`")]
+fn assert_logged_fails_without_a_match() {
+    assert_logged!(LogLevel::Warn, "disk", || log::error!("disk full"));
+}
@@ -0,0 +1,121 @@
+use nxlog_task::task_1::{self, memory_sink, LogLevel, OptionExt, ResultExt};
+use std::fmt;
+
+// The levels and messages of the records logged by `f`
+fn captured(f: impl FnOnce()) -> Vec<(LogLevel, String)> {
+    let ((), records) = memory_sink::capture(f);
+    records
+        .into_iter()
+        .map(|record| (record.level, record.message))
+        .collect()
+}
+
+#[derive(Debug)]
+struct Error {
+    message: String,
+    source: Option<Box<Error>>,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
+
+fn failing() -> Result<u32, Error> {
+    Err(Error {
+        message: "config invalid".to_string(),
+        source: Some(Box::new(Error {
+            message: "file missing".to_string(),
+            source: None,
+        })),
+    })
+}
+
+#[test]
+fn log_err_logs_the_source_chain() {
+    let records = captured(|| {
+        assert_eq!(Ok::<u32, Error>(1).log_err().unwrap(), 1);
+        assert!(failing().log_err().is_err());
+        assert!(failing().log_err_msg("startup").is_err());
+    });
+
+    assert_eq!(
+        records,
+        [
+            (LogLevel::Error, "config invalid: file missing".to_string()),
+            (
+                LogLevel::Error,
+                "startup: config invalid: file missing".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn log_err_to_logs_to_the_given_logger() {
+    let sink = memory_sink::MemorySink::new(log::LevelFilter::Error, 16);
+    let records = captured(|| {
+        assert_eq!(Ok::<u32, Error>(1).log_err_to(&sink).unwrap(), 1);
+        assert!(failing().log_err_to(&sink).is_err());
+        assert!(failing().log_err_msg_to(&sink, "startup").is_err());
+    });
+
+    // Not through the facade
+    assert!(records.is_empty());
+    let messages: Vec<_> = sink
+        .records()
+        .into_iter()
+        .map(|record| (record.level, record.message))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (LogLevel::Error, "config invalid: file missing".to_string()),
+            (
+                LogLevel::Error,
+                "startup: config invalid: file missing".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn log_none_logs_only_the_nones() {
+    let records = captured(|| {
+        let kept: Vec<_> = ["a", "", "b"]
+            .into_iter()
+            .filter_map(|value| {
+                Some(value)
+                    .filter(|value| !value.is_empty())
+                    .log_none(LogLevel::Debug, "empty value skipped")
+            })
+            .collect();
+        assert_eq!(kept, ["a", "b"]);
+    });
+
+    assert_eq!(
+        records,
+        [(LogLevel::Debug, "empty value skipped".to_string())]
+    );
+}
+
+#[test]
+fn log_error_chain_logs_every_source_as_a_field() {
+    let error = failing().unwrap_err();
+
+    let records = captured(|| task_1::log_error_chain(&error));
+
+    assert_eq!(records.len(), 1);
+    let (level, fields) = &records[0];
+    assert_eq!(*level, LogLevel::Error);
+    assert!(fields.starts_with("error=\"config invalid\" cause.1=\"file missing\""));
+}
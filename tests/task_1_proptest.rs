@@ -0,0 +1,125 @@
+use nxlog_task::task_1::access_log::{self, AccessRecord};
+use nxlog_task::task_1::log_backend;
+use nxlog_task::task_1::signature::SigningKey;
+use nxlog_task::task_1::{
+    self, hash_chain, signature, Anonymize, LogError, LogLevel, LogOptions, MultiLine,
+};
+use proptest::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+// Arbitrary text biased towards the characters the formats and parsers care about
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[ a-f0-9#@.=\"\\\\\n\r\t\x1b\u{202e}{}]{0,80}",
+        "(key=[a-z@.]{0,8} ){0,4}",
+    ]
+}
+
+// A fresh file path per case, as the cases of a test run in the same process and directory
+fn case_path(name: &str) -> PathBuf {
+    static CASE: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("nxlog_task_proptest_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create test directory");
+    dir.join(format!(
+        "{}_{}.txt",
+        name,
+        CASE.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+proptest! {
+    #[test]
+    fn escaped_messages_have_no_control_chars(message in text()) {
+        let escaped = task_1::escape_control_chars(&message);
+        let mut chars = escaped.chars().peekable();
+        while let Some(c) = chars.next() {
+            let newline = c == '\n' || (c == '\r' && chars.peek() == Some(&'\n'));
+            prop_assert!(!c.is_control() || newline, "{:?}", escaped);
+        }
+    }
+
+    #[test]
+    fn multi_line_records_stay_one_record(message in text()) {
+        // Continuation lines never look like the start of a record
+        let indented = MultiLine::Indent.join("[INFO]", &message);
+        prop_assert!(indented.starts_with("[INFO]"));
+        prop_assert!(indented.split('\n').skip(1).all(|line| line.starts_with("    ")));
+
+        prop_assert!(!MultiLine::Escape.join("[INFO]", &message).contains('\n'));
+        let _ = MultiLine::Frame.join("[INFO]", &message);
+    }
+
+    #[test]
+    fn anonymize_never_panics(message in text(), key in "[a-z]{0,4}") {
+        let transforms = [
+            Anonymize::HashIpAddresses { salt: "salt".to_string() },
+            Anonymize::MaskEmails,
+            Anonymize::DropKeys(vec![key]),
+        ];
+        let _ = task_1::anonymize(&message, &transforms);
+    }
+
+    #[test]
+    fn formatting_never_panics(message in text()) {
+        let options = LogOptions {
+            anonymize: vec![Anonymize::MaskEmails],
+            ..LogOptions::default()
+        };
+        let mut out = String::new();
+        task_1::format_record_into(&options, &mut out, LogLevel::Warn, &message);
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_696_161_600);
+        let json = log_backend::format_json(time, log::Level::Warn, "target", &message);
+        let record: log_backend::JsonRecord = serde_json::from_str(&json).unwrap();
+        prop_assert_eq!(record.message, message.clone());
+
+        let record = AccessRecord {
+            client: &message,
+            user: Some(&message),
+            time,
+            method: &message,
+            path: &message,
+            protocol: "HTTP/1.1",
+            status: 200,
+            bytes: Some(0),
+            referer: Some(&message),
+            user_agent: Some(&message),
+            latency: None,
+        };
+        let mut out = String::new();
+        access_log::format_combined(&record, &mut out);
+        prop_assert!(!out.contains('\n'));
+    }
+
+    #[test]
+    fn json_lines_reader_never_panics(content in prop::collection::vec(any::<u8>(), 0..256)) {
+        for record in log_backend::read_json_lines(content.as_slice()) {
+            let _ = record;
+        }
+    }
+
+    #[test]
+    fn verifiers_never_panic(content in prop::collection::vec(any::<u8>(), 0..256)) {
+        let path = case_path("verify");
+        fs::write(&path, &content).unwrap();
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let _ = hash_chain::verify(&path);
+        let _ = signature::verify(&path, &key.verifying_key());
+
+        // The chain is appended to whatever the file already holds
+        let options = LogOptions {
+            file_path: path.clone(),
+            hash_chain: true,
+            ..LogOptions::default()
+        };
+        let written =
+            task_1::write_to_log_with(&options, task_1::LogType::FileSystem, LogLevel::Info, "next");
+        prop_assert!(!matches!(written, Err(LogError::Io { .. })), "{:?}", written);
+        fs::remove_file(path).unwrap();
+    }
+}
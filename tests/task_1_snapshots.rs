@@ -0,0 +1,140 @@
+use nxlog_task::task_1::access_log::{self, AccessRecord};
+use nxlog_task::task_1::fixture::{self, RecordFixture};
+use nxlog_task::task_1::log_backend::{self, Format, LogBackend, Theme};
+use nxlog_task::task_1::{LogLevel, LogOptions, LogType, MultiLine};
+use std::fs;
+use std::time::Duration;
+
+// Writes every edge case with `write` to a file of its own and returns the files as they are,
+// each under the name of its case
+fn written(
+    name: &str,
+    options: &LogOptions,
+    write: impl Fn(&LogOptions, &RecordFixture),
+) -> String {
+    let dir = std::env::temp_dir().join(format!(
+        "nxlog_task_snapshots_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Failed to create test directory");
+
+    let mut snapshot = String::new();
+    for (case, record) in fixture::edge_cases() {
+        let options = LogOptions {
+            file_path: dir.join(format!("{}.txt", case)),
+            ..options.clone()
+        };
+        write(&options, &record);
+        let content = fs::read_to_string(&options.file_path).expect("Failed to read log file");
+        snapshot.push_str(&format!("--- {}\n{}", case, content));
+    }
+
+    fs::remove_dir_all(dir).expect("Failed to remove test directory");
+    snapshot
+}
+
+fn logged_by(name: &str, format: Format, level: log::Level) -> String {
+    written(name, &LogOptions::default(), |options, record| {
+        let backend = LogBackend::new(log::LevelFilter::Trace, LogType::FileSystem)
+            .with_format(format)
+            .with_clock(fixture::clock())
+            .with_options(options.clone());
+        record
+            .clone()
+            .with_level(level)
+            .with_target("my_crate::net")
+            .log(&backend);
+    })
+}
+
+#[test]
+fn plain() {
+    insta::assert_snapshot!(logged_by("plain", Format::Plain, log::Level::Warn));
+}
+
+// Only the console output is styled, so there is no file to read it back from
+#[test]
+fn plain_dark() {
+    let snapshot: String = fixture::edge_cases()
+        .iter()
+        .map(|(case, record)| {
+            let line = log_backend::format_plain(
+                LogLevel::Error,
+                "my_crate::net",
+                &record.message(),
+                &Theme::dark(),
+            );
+            format!("--- {}\n{}\n", case, line)
+        })
+        .collect();
+    insta::assert_snapshot!(snapshot);
+}
+
+#[test]
+fn env_logger() {
+    insta::assert_snapshot!(logged_by("env_logger", Format::EnvLogger, log::Level::Info));
+}
+
+#[test]
+fn json() {
+    insta::assert_snapshot!(logged_by("json", Format::Json, log::Level::Debug));
+}
+
+#[test]
+fn multi_line() {
+    for multi_line in [
+        MultiLine::Raw,
+        MultiLine::Indent,
+        MultiLine::Escape,
+        MultiLine::Frame,
+    ] {
+        let options = LogOptions {
+            multi_line,
+            ..LogOptions::default()
+        };
+        insta::assert_snapshot!(
+            format!("multi_line_{:?}", multi_line),
+            written(
+                &format!("multi_line_{:?}", multi_line),
+                &options,
+                |options, record| {
+                    nxlog_task::task_1::write_to_log_with(
+                        options,
+                        LogType::FileSystem,
+                        LogLevel::Info,
+                        record.message(),
+                    )
+                    .expect("Failed to write to log");
+                }
+            )
+        );
+    }
+}
+
+#[test]
+fn combined() {
+    insta::assert_snapshot!(written(
+        "combined",
+        &LogOptions::default(),
+        |options, record| {
+            let message = record.message();
+            let record = AccessRecord {
+                client: "192.168.0.12",
+                user: Some(message),
+                time: fixture::time(),
+                method: "GET",
+                path: message,
+                protocol: "HTTP/1.1",
+                status: 200,
+                bytes: Some(512),
+                referer: None,
+                user_agent: Some(message),
+                latency: Some(Duration::from_micros(1500)),
+            };
+            access_log::write_access_log(options, LogType::FileSystem, &record)
+                .expect("Failed to write the access log");
+        }
+    ));
+}
@@ -0,0 +1,85 @@
+use nxlog_task::task_2::{self, NonBlank};
+use proptest::prelude::*;
+use std::collections::HashSet;
+
+// Short strings from a small alphabet, so empty, blank and duplicate values are common
+fn input() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec("[ ab]{0,3}", 0..64)
+}
+
+fn assert_contiguous<T>(output: &[(usize, T)]) {
+    for (expected, (index, _)) in output.iter().enumerate() {
+        assert_eq!(*index, expected);
+    }
+}
+
+proptest! {
+    #[test]
+    fn versions_are_equivalent(input in input()) {
+        let output = task_2::foo(input.clone());
+
+        prop_assert_eq!(&output, &task_2::v1::foo(input.clone()));
+        prop_assert_eq!(&output, &task_2::v2::foo(input.clone()));
+        prop_assert_eq!(&output, &task_2::foo_vec(input.clone()));
+
+        let borrowed: Vec<_> = output.iter().map(|(i, v)| (*i, v.as_str())).collect();
+        prop_assert_eq!(&borrowed, &task_2::foo_slice(&input));
+    }
+
+    #[test]
+    fn indices_are_contiguous(input in input()) {
+        assert_contiguous(&task_2::foo(input.clone()));
+        assert_contiguous(&task_2::foo_dedup(input.clone()));
+        assert_contiguous(&task_2::foo_by(input.clone(), &NonBlank));
+        assert_contiguous(&task_2::foo_with(input, |v| v.parse::<u8>().ok()));
+    }
+
+    #[test]
+    fn ordering_is_preserved(input in input()) {
+        let expected: Vec<_> = input.iter().filter(|v| !v.trim().is_empty()).collect();
+        let output: Vec<_> = task_2::foo_by(input.iter(), &NonBlank)
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect();
+
+        prop_assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn original_indices_point_into_input(input in input()) {
+        let output = task_2::foo_with_original_indices(&input, |v| (!v.is_empty()).then_some(v.len()));
+
+        for window in output.windows(2) {
+            prop_assert!(window[0].0 < window[1].0);
+        }
+        for (index, len) in output {
+            prop_assert_eq!(input[index].len(), len);
+        }
+    }
+
+    #[test]
+    fn partition_covers_input(input in input()) {
+        let (accepted, rejected) = task_2::partition_foo_by(input.clone(), &NonBlank);
+
+        prop_assert_eq!(accepted.len() + rejected.len(), input.len());
+        prop_assert_eq!(&accepted, &task_2::foo_by(input.clone(), &NonBlank));
+        for (position, value) in rejected {
+            prop_assert_eq!(&input[position], &value);
+        }
+    }
+
+    #[test]
+    fn dedup_has_no_duplicates(input in input()) {
+        let output = task_2::foo_dedup(input.clone());
+        let unique: HashSet<_> = input.iter().collect();
+
+        prop_assert_eq!(output.len(), unique.len());
+        prop_assert_eq!(output.iter().map(|(_, v)| v).collect::<HashSet<_>>(), unique);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_foo_matches_foo(input in input()) {
+        prop_assert_eq!(task_2::par_foo(input.clone()), task_2::foo(input));
+    }
+}